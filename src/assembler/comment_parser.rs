@@ -0,0 +1,130 @@
+use crate::assembler::{AssemblerError, AssemblerErrorKind};
+
+/// Replaces `;` line comments and `/* */` block comments with spaces. Line breaks are kept and
+/// every other byte stays where it was, so a position found in the result holds for `source` too.
+/// Comment markers inside string and character literals are left alone. An unterminated block comment runs to
+/// the end of the source and is reported alongside the stripped text.
+pub fn strip_comments(source: &str) -> (String, Option<AssemblerError>) {
+    let mut result = String::with_capacity(source.len());
+    let mut chars = source.char_indices().peekable();
+    // The quote of the string or character literal being read
+    let mut quote = None;
+    let mut open_block = None;
+
+    while let Some((index, c)) = chars.next() {
+        if open_block.is_some() {
+            if c == '*' && chars.peek().map(|&(_, next)| next) == Some('/') {
+                chars.next();
+                result.push_str("  ");
+                open_block = None;
+            } else {
+                blank(&mut result, c);
+            }
+            continue;
+        }
+
+        if let Some(open) = quote {
+            result.push(c);
+            match c {
+                '\\' => {
+                    if let Some((_, escaped)) = chars.next() {
+                        result.push(escaped);
+                    }
+                }
+                '\n' => quote = None,
+                c if c == open => quote = None,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => {
+                quote = Some(c);
+                result.push(c);
+            }
+            ';' => {
+                blank(&mut result, c);
+                while let Some(&(_, next)) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    blank(&mut result, next);
+                    chars.next();
+                }
+            }
+            '/' if chars.peek().map(|&(_, next)| next) == Some('*') => {
+                chars.next();
+                result.push_str("  ");
+                open_block = Some(index);
+            }
+            c => result.push(c),
+        }
+    }
+
+    let error = open_block.map(|start| {
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line = source[..start].matches('\n').count() + 1;
+        let snippet = source[line_start..].lines().next().unwrap_or("");
+        AssemblerError::new(
+            AssemblerErrorKind::UnterminatedComment,
+            line,
+            snippet,
+            start - line_start,
+            2,
+        )
+    });
+    (result, error)
+}
+
+/// Pushes the blank a commented out character is replaced with.
+fn blank(result: &mut String, c: char) {
+    match c {
+        '\n' | '\r' => result.push(c),
+        c => result.push_str(&" ".repeat(c.len_utf8())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_line_comments() {
+        let (code, error) = strip_comments("hlt ; stop here\n; whole line\nret");
+        assert_eq!(code, "hlt            \n            \nret");
+        assert_eq!(error, None);
+    }
+
+    #[test]
+    fn test_strip_block_comments() {
+        let source = "load /* multi\nline */ $0 #1\n/**/hlt";
+        let (code, error) = strip_comments(source);
+        assert_eq!(code, "load         \n        $0 #1\n    hlt");
+        assert_eq!(code.len(), source.len());
+        assert_eq!(error, None);
+    }
+
+    #[test]
+    fn test_comment_markers_in_strings() {
+        let source = "msg: .asciiz \"a;b /* \\\" ;\" ; note";
+        let (code, _) = strip_comments(source);
+        assert_eq!(code, "msg: .asciiz \"a;b /* \\\" ;\"       ");
+    }
+
+    #[test]
+    fn test_comment_markers_in_characters() {
+        let (code, error) = strip_comments("load $0 #';'\nload $1 #'\"' ; quote\nload $2 #'\\''");
+        assert_eq!(code, "load $0 #';'\nload $1 #'\"'        \nload $2 #'\\''");
+        assert_eq!(error, None);
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let (code, error) = strip_comments("hlt\n  ret /* never\nclosed");
+        assert_eq!(code, "hlt\n  ret         \n      ");
+        let error = error.unwrap();
+        assert_eq!(error.kind, AssemblerErrorKind::UnterminatedComment);
+        assert_eq!((error.line, error.column, error.len), (2, 7, 2));
+    }
+}
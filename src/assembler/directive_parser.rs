@@ -0,0 +1,297 @@
+use nom::types::CompleteStr;
+use nom::*;
+
+use crate::assembler::error::{first_word, LineError};
+use crate::assembler::label_parser::{label_declaration, label_usage};
+use crate::assembler::operand_parser::{integer_literal, integer_operand, scan_integer};
+use crate::assembler::{AssemblerErrorKind, SymbolTable, Token, MAX_SECTION_LEN};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Directive {
+    /// `.data` starts the read-only data section.
+    Data,
+    /// `.code` starts the instruction section.
+    Code,
+    /// `.asciiz "text"` emits the bytes of the string followed by a zero byte.
+    Asciiz(String),
+    /// `.word 123` emits a 32-bit big-endian word. Takes an integer or a label usage.
+    Word(Token),
+    /// `.space N` emits N zero bytes.
+    Space(u32),
+    /// `.byte 255` emits a single byte. Also allowed in the code section, where the disassembler
+    /// writes bytes that do not decode as an instruction with it.
+    Byte(u8),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AssemblerDirective {
+    label: Option<String>,
+    directive: Directive,
+}
+
+impl AssemblerDirective {
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn directive(&self) -> &Directive {
+        &self.directive
+    }
+
+    pub fn label_usages(&self) -> Vec<&str> {
+        match &self.directive {
+            Directive::Word(Token::LabelUsage { name }) => vec![name.as_str()],
+            _ => vec![],
+        }
+    }
+
+    /// Number of bytes `to_bytes` produces, known before labels are resolved.
+    pub fn encoded_len(&self) -> usize {
+        match &self.directive {
+            Directive::Data | Directive::Code => 0,
+            Directive::Asciiz(text) => text.len() + 1,
+            Directive::Word(_) => 4,
+            Directive::Space(len) => *len as usize,
+            Directive::Byte(_) => 1,
+        }
+    }
+
+    /// Encodes the directive. Labels missing from `symbols` encode as 0, as in
+    /// `AssemblerInstruction::to_bytes`.
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
+        match &self.directive {
+            Directive::Data | Directive::Code => vec![],
+            Directive::Asciiz(text) => {
+                let mut results = text.as_bytes().to_vec();
+                results.push(0);
+                results
+            }
+            Directive::Word(Token::LabelUsage { name }) => {
+                let value = symbols.symbol_value(name).unwrap_or(0);
+                value.to_be_bytes().to_vec()
+            }
+            Directive::Word(Token::IntegerOperand { value }) => value.to_be_bytes().to_vec(),
+            Directive::Word(_) => vec![0; 4],
+            Directive::Space(len) => vec![0; *len as usize],
+            Directive::Byte(value) => vec![*value],
+        }
+    }
+}
+
+/// Parses a double-quoted string. Supports the escapes `\n`, `\t`, `\0`, `\\` and `\"`.
+fn string_literal(input: CompleteStr) -> IResult<CompleteStr, String> {
+    let error = Err(Err::Error(error_position!(input, ErrorKind::Custom(0))));
+    let mut chars = input.char_indices();
+    match chars.next() {
+        Some((_, '"')) => {}
+        _ => return error,
+    }
+
+    let mut value = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((CompleteStr(&input[index + 1..]), value)),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, '0')) => value.push('\0'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, '"')) => value.push('"'),
+                _ => return error,
+            },
+            '\n' => return error,
+            c => value.push(c),
+        }
+    }
+    error
+}
+
+// Bare decimal numbers as used by `.space` and `.word`
+named!(unsigned_number<CompleteStr, u32>,
+    map_res!(digit, |d: CompleteStr| d.parse::<u32>())
+);
+
+named!(byte_value<CompleteStr, u8>,
+    map_res!(digit, |d: CompleteStr| d.parse::<u8>())
+);
+
+named!(word_operand<CompleteStr, Token>,
+    alt!(
+        integer_operand |
+        label_usage |
+        ws!(integer_literal) => { Token::operand }
+    )
+);
+
+named!(directive_kind<CompleteStr, Directive>,
+    alt!(
+        do_parse!(tag!(".data") >> (Directive::Data)) |
+        do_parse!(tag!(".code") >> (Directive::Code)) |
+        do_parse!(tag!(".asciiz") >> space >> text: string_literal >> (Directive::Asciiz(text))) |
+        do_parse!(tag!(".word") >> value: word_operand >> (Directive::Word(value))) |
+        do_parse!(tag!(".space") >> space >> len: unsigned_number >> (Directive::Space(len))) |
+        do_parse!(tag!(".byte") >> space >> value: byte_value >> (Directive::Byte(value)))
+    )
+);
+
+/// Directives the assembler understands, used to tell a misspelt directive from a bad operand.
+const DIRECTIVES: &[&str] = &[".data", ".code", ".asciiz", ".word", ".space", ".byte"];
+
+/// Parses an assembler directive, which we preface with `.` in our assembly language:
+/// msg: .asciiz "Hello"
+/// Like `instruction`, anything after the directive is left to the caller.
+pub fn directive(input: CompleteStr) -> Result<(CompleteStr, AssemblerDirective), LineError> {
+    let (input, label) = match label_declaration(input) {
+        Ok((rest, label)) => (rest, Some(label)),
+        Err(_) => (input, None),
+    };
+    let start = input.0.trim_start();
+    let name = first_word(start);
+    if !name.starts_with('.') {
+        return Err(LineError::unexpected(start, "directive"));
+    }
+    if !DIRECTIVES.contains(&name) {
+        return Err(LineError::new(
+            start,
+            AssemblerErrorKind::UnknownDirective(name.to_string()),
+        ));
+    }
+
+    let at = start[name.len()..].trim_start();
+    let space_out_of_range = || {
+        LineError::new(
+            at,
+            AssemblerErrorKind::IntegerOutOfRange {
+                text: first_word(at).to_string(),
+                min: 0,
+                max: i64::from(MAX_SECTION_LEN),
+            },
+        )
+    };
+    match directive_kind(CompleteStr(start)) {
+        Ok((_, Directive::Space(len))) if len > MAX_SECTION_LEN => Err(space_out_of_range()),
+        Ok((rest, directive)) => Ok((rest, AssemblerDirective { label, directive })),
+        Err(_) => {
+            let word = first_word(at);
+            let literal = word.trim_start_matches('#');
+            if name == ".space" && !word.is_empty() && word.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(space_out_of_range());
+            }
+            if name == ".word" && scan_integer(literal).map(|(len, _)| len) == Some(literal.len()) {
+                return Err(LineError::new(
+                    at,
+                    AssemblerErrorKind::IntegerOutOfRange {
+                        text: word.to_string(),
+                        min: i64::from(i32::MIN),
+                        max: i64::from(i32::MAX),
+                    },
+                ));
+            }
+            let expected = match name {
+                ".asciiz" => "string",
+                ".word" => "integer or label",
+                ".byte" => "byte value",
+                _ => "size",
+            };
+            Err(LineError::unexpected(at, expected))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_section_directives() {
+        let result = directive(CompleteStr(".data"));
+        let (rest, data) = result.unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(data.directive(), &Directive::Data);
+        let (_, code) = directive(CompleteStr("  .code")).unwrap();
+        assert_eq!(code.directive(), &Directive::Code);
+    }
+
+    #[test]
+    fn test_parse_asciiz() {
+        let result = directive(CompleteStr("msg: .asciiz \"Hi \\\"there\\\"\\n\""));
+        let (rest, d) = result.unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(d.label(), Some("msg"));
+        assert_eq!(
+            d.directive(),
+            &Directive::Asciiz("Hi \"there\"\n".to_string())
+        );
+        assert_eq!(d.encoded_len(), 12);
+        assert_eq!(d.to_bytes(&SymbolTable::new()).last(), Some(&0));
+
+        let result = directive(CompleteStr(".asciiz \"unterminated\n"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_word() {
+        let (_, d) = directive(CompleteStr(".word 258")).unwrap();
+        assert_eq!(d.to_bytes(&SymbolTable::new()), vec![0, 0, 1, 2]);
+
+        let (_, d) = directive(CompleteStr(".word -1")).unwrap();
+        assert_eq!(d.to_bytes(&SymbolTable::new()), vec![255, 255, 255, 255]);
+
+        let (_, d) = directive(CompleteStr(".word 0xFF_FF")).unwrap();
+        assert_eq!(d.to_bytes(&SymbolTable::new()), vec![0, 0, 255, 255]);
+
+        let (_, d) = directive(CompleteStr(".word #7")).unwrap();
+        assert_eq!(d.directive(), &Directive::Word(Token::operand(7)));
+
+        let (_, d) = directive(CompleteStr(".word @start")).unwrap();
+        assert_eq!(d.label_usages(), vec!["start"]);
+    }
+
+    #[test]
+    fn test_parse_space() {
+        let (_, d) = directive(CompleteStr(".space 3")).unwrap();
+        assert_eq!(d.to_bytes(&SymbolTable::new()), vec![0, 0, 0]);
+
+        let result = directive(CompleteStr(".space"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_byte() {
+        let (_, d) = directive(CompleteStr("b: .byte 200")).unwrap();
+        assert_eq!(d.directive(), &Directive::Byte(200));
+        assert_eq!(d.encoded_len(), 1);
+        assert_eq!(d.to_bytes(&SymbolTable::new()), vec![200]);
+    }
+
+    #[test]
+    fn test_parse_directive_errors() {
+        let e = directive(CompleteStr(".half 1")).unwrap_err();
+        assert_eq!(
+            e.kind,
+            AssemblerErrorKind::UnknownDirective(".half".to_string())
+        );
+
+        let e = directive(CompleteStr(".byte 256")).unwrap_err();
+        assert_eq!(e.kind.to_string(), "expected byte value, found `256`");
+
+        let e = directive(CompleteStr(".space -1")).unwrap_err();
+        assert_eq!(e.at, "-1");
+        assert_eq!(e.kind.to_string(), "expected size, found `-1`");
+
+        for size in &["16777217", "99999999999"] {
+            let source = format!(".space {}", size);
+            let e = directive(CompleteStr(&source)).unwrap_err();
+            assert_eq!(
+                e.kind.to_string(),
+                format!("integer `{}` is out of range (0 to 16777216)", size)
+            );
+        }
+
+        let e = directive(CompleteStr(".word 99999999999")).unwrap_err();
+        assert_eq!(
+            e.kind.to_string(),
+            "integer `99999999999` is out of range (-2147483648 to 2147483647)"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::assembler::{Section, MAX_SECTION_LEN};
+
+/// What went wrong, without the position it went wrong at.
+#[derive(Debug, PartialEq, Clone)]
+pub enum AssemblerErrorKind {
+    /// The source does not match the grammar at this point.
+    Unexpected { expected: String, found: String },
+    /// The mnemonic does not name an opcode.
+    UnknownInstruction(String),
+    /// A `/*` without a matching `*/`.
+    UnterminatedComment,
+    /// A `.` directive the assembler does not know.
+    UnknownDirective(String),
+    /// A register operand past the end of the register file.
+    RegisterOutOfRange(String),
+    /// An integer that does not fit in the operand it is written in, which holds `min` to `max`.
+    IntegerOutOfRange { text: String, min: i64, max: i64 },
+    /// A label was referenced with `@name` but never declared.
+    UnknownLabel(String),
+    /// The same label was declared more than once.
+    DuplicateLabel(String),
+    /// A label resolves to an offset that does not fit in a 16-bit operand.
+    LabelOutOfRange(String),
+    /// An instruction appears after `.data` without a `.code` in between.
+    InstructionInDataSection,
+    /// `.asciiz`, `.word` or `.space` appears outside of a `.data` section.
+    DataOutsideDataSection,
+    /// A `.data` section in a program assembled without one, see `Assembler::assemble_code`.
+    DataSectionNotAllowed,
+    /// The statement makes its section longer than `MAX_SECTION_LEN` bytes.
+    SectionTooLarge(Section),
+}
+
+impl fmt::Display for AssemblerErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerErrorKind::Unexpected { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            AssemblerErrorKind::UnknownInstruction(name) => {
+                write!(f, "unknown instruction `{}`", name)
+            }
+            AssemblerErrorKind::UnterminatedComment => write!(f, "unterminated block comment"),
+            AssemblerErrorKind::UnknownDirective(name) => write!(f, "unknown directive `{}`", name),
+            AssemblerErrorKind::RegisterOutOfRange(text) => {
+                write!(f, "register `{}` is out of range", text)
+            }
+            AssemblerErrorKind::IntegerOutOfRange { text, min, max } => {
+                write!(f, "integer `{}` is out of range ({} to {})", text, min, max)
+            }
+            AssemblerErrorKind::UnknownLabel(name) => write!(f, "unknown label `{}`", name),
+            AssemblerErrorKind::DuplicateLabel(name) => {
+                write!(f, "label `{}` declared twice", name)
+            }
+            AssemblerErrorKind::LabelOutOfRange(name) => {
+                write!(f, "label `{}` is out of 16-bit range", name)
+            }
+            AssemblerErrorKind::InstructionInDataSection => {
+                write!(f, "instructions are not allowed in the .data section")
+            }
+            AssemblerErrorKind::DataOutsideDataSection => {
+                write!(f, "data directives are only allowed in the .data section")
+            }
+            AssemblerErrorKind::DataSectionNotAllowed => {
+                write!(
+                    f,
+                    "a .data section needs a bytecode file and is not allowed here"
+                )
+            }
+            AssemblerErrorKind::SectionTooLarge(section) => {
+                let name = match section {
+                    Section::Code => ".code",
+                    Section::Data => ".data",
+                };
+                write!(
+                    f,
+                    "the {} section grows past {} bytes",
+                    name, MAX_SECTION_LEN
+                )
+            }
+        }
+    }
+}
+
+/// Reason a source file could not be assembled, with the place in the source it refers to.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AssemblerError {
+    pub kind: AssemblerErrorKind,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+    /// Number of characters the caret underlines, at least one.
+    pub len: usize,
+    /// The source line the error was found on.
+    pub snippet: String,
+}
+
+impl AssemblerError {
+    /// Error for the text starting at byte `offset` of `snippet` and spanning `len` bytes.
+    pub fn new(
+        kind: AssemblerErrorKind,
+        line: usize,
+        snippet: &str,
+        offset: usize,
+        len: usize,
+    ) -> Self {
+        let offset = offset.min(snippet.len());
+        let end = (offset + len).min(snippet.len());
+        AssemblerError {
+            kind,
+            line,
+            column: snippet[..offset].chars().count() + 1,
+            len: snippet[offset..end].chars().count().max(1),
+            snippet: snippet.to_string(),
+        }
+    }
+}
+
+// Rendered like rustc diagnostics:
+//
+// error: unknown label `nowhere`
+//  --> 1:6
+//   |
+// 1 | call @nowhere
+//   |      ^^^^^^^^
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        // Tabs are kept so the caret lines up with the snippet however the terminal renders them.
+        let indent: String = self
+            .snippet
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        writeln!(f, "error: {}", self.kind)?;
+        writeln!(f, "{}--> {}:{}", gutter, self.line, self.column)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.snippet)?;
+        write!(f, "{} | {}{}", gutter, indent, "^".repeat(self.len))
+    }
+}
+
+impl Error for AssemblerError {}
+
+/// Error found while parsing a single line, located by the input that remained when it was
+/// found. Turned into an `AssemblerError` once the line is known.
+#[derive(Debug, PartialEq)]
+pub struct LineError<'a> {
+    pub at: &'a str,
+    pub len: usize,
+    pub kind: AssemblerErrorKind,
+}
+
+impl<'a> LineError<'a> {
+    /// Error covering the first word of `at`.
+    pub fn new(at: &'a str, kind: AssemblerErrorKind) -> Self {
+        LineError {
+            at,
+            len: first_word(at).len(),
+            kind,
+        }
+    }
+
+    /// `expected` was wanted but the first word of `at` was found instead.
+    pub fn unexpected(at: &'a str, expected: &str) -> Self {
+        let found = match first_word(at) {
+            "" => "end of line".to_string(),
+            word => format!("`{}`", word),
+        };
+        LineError::new(
+            at,
+            AssemblerErrorKind::Unexpected {
+                expected: expected.to_string(),
+                found,
+            },
+        )
+    }
+
+    /// Places the error in line `number`. `parsed` is the text `at` is part of and `line` the
+    /// line as written, which may only differ from it in blanked out comments.
+    pub fn at_line(self, number: usize, parsed: &str, line: &str) -> AssemblerError {
+        let offset = (self.at.as_ptr() as usize).saturating_sub(parsed.as_ptr() as usize);
+        AssemblerError::new(self.kind, number, line, offset, self.len)
+    }
+}
+
+/// Text up to the next whitespace.
+pub fn first_word(input: &str) -> &str {
+    input.split_whitespace().next().unwrap_or("")
+}
@@ -1,10 +1,15 @@
-use nom::*;
+use std::fmt;
+
 use nom::types::CompleteStr;
+use nom::*;
 
+use crate::assembler::error::{first_word, LineError};
+use crate::assembler::label_parser::label_declaration;
 use crate::assembler::opcode_parser::opcode;
-use crate::assembler::operand_parser::integer_operand;
-use crate::assembler::register_parser::register;
-use crate::assembler::Token;
+use crate::assembler::operand_parser::{operand, scan_integer};
+use crate::assembler::register_parser::{float_register, register};
+use crate::assembler::{AssemblerErrorKind, SymbolTable, Token};
+use crate::instructions::{Encoding, Opcode, OperandKind};
 
 #[derive(Debug, PartialEq)]
 pub struct AssemblerInstruction {
@@ -16,123 +21,235 @@ pub struct AssemblerInstruction {
 }
 
 impl AssemblerInstruction {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut results = vec![];
+    /// Builds an instruction from already decoded parts. Takes at most three operands.
+    pub fn new(label: Option<String>, code: Opcode, operands: Vec<Token>) -> Self {
+        let mut operands = operands.into_iter();
+        AssemblerInstruction {
+            label,
+            opcode: Token::opcode(code),
+            operand1: operands.next(),
+            operand2: operands.next(),
+            operand3: operands.next(),
+        }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
 
+    /// Names of all labels this instruction references with `@name`.
+    pub fn label_usages(&self) -> Vec<&str> {
+        self.operands()
+            .filter_map(|t| match t {
+                Token::LabelUsage { name } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Number of bytes `to_bytes` produces, known before labels are resolved.
+    pub fn encoded_len(&self) -> usize {
+        self.encoding().width
+    }
+
+    fn encoding(&self) -> Encoding {
         match &self.opcode {
-            Token::Op { code } => {
-                results.push(code.clone() as u8);
-            }
-            _ => {
-                println!("Found non opcode `{:?}` as opcode", self.opcode);
-                std::process::exit(1);
-            }
+            Token::Op { code } => code.encoding(),
+            _ => Opcode::IGL.encoding(),
         }
+    }
 
-        //Extract operand
-        for operand in vec![&self.operand1, &self.operand2, &self.operand3] {
-            match operand {
-                Some(t) => AssemblerInstruction::extract_operand(t, &mut results),
-                None => {}
-            }
+    fn operands(&self) -> impl Iterator<Item = &Token> {
+        self.operand1
+            .iter()
+            .chain(&self.operand2)
+            .chain(&self.operand3)
+    }
+
+    /// Encodes the instruction with its opcode's encoding. Labels missing from `symbols` encode as
+    /// 0; the assembler reports them before it gets this far.
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
+        let mut results = vec![];
+
+        if let Token::Op { code } = &self.opcode {
+            results.push(code.clone() as u8);
         }
 
+        let encoding = self.encoding();
+        for (kind, t) in encoding.operands.iter().zip(self.operands()) {
+            let value = match t {
+                Token::Register { reg_num } | Token::FloatRegister { reg_num } => {
+                    i64::from(*reg_num)
+                }
+                Token::IntegerOperand { value } if *kind == OperandKind::Float => {
+                    f64::from(*value).to_bits() as i64
+                }
+                Token::IntegerOperand { value } => i64::from(*value),
+                Token::FloatOperand { value } => value.to_bits() as i64,
+                Token::LabelUsage { name } => i64::from(symbols.symbol_value(name).unwrap_or(0)),
+                Token::Op { .. } => 0,
+            };
+            kind.encode(value, &mut results);
+        }
+
+        //Pad instructions like EQ whose encoding has an unused trailing byte
+        results.resize(encoding.width, 0);
         results
     }
+}
 
-    fn extract_operand(t: &Token, results: &mut Vec<u8>) {
-        match t {
-            Token::Register { reg_num } => {
-                results.push(*reg_num);
-            }
-            Token::IntegerOperand { value } => {
-                let converted = *value as u16;
-                let byte1 = converted;
-                let byte2 = converted >> 8;
-                results.push(byte2 as u8);
-                results.push(byte1 as u8);
-            }
-            _ => {
-                println!("Found non opcode `{:?}` as opcode", t);
-                std::process::exit(1);
-            }
-        };
+impl fmt::Display for AssemblerInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(label) = &self.label {
+            write!(f, "{}: ", label)?;
+        }
+        write!(f, "{}", self.opcode)?;
+        for t in self.operands() {
+            write!(f, " {}", t)?;
+        }
+        Ok(())
     }
 }
 
-named!(pub instruction_one<CompleteStr, AssemblerInstruction>,
-    do_parse!(
-        o: opcode >>
-        r: register >>
-        i: integer_operand >>
-        (
-            AssemblerInstruction {
-                label: None,
-                opcode: o,
-                operand1: Some(r),
-                operand2: Some(i),
-                operand3: None
-            }
-        )
+named!(instruction_operand<CompleteStr, Token>,
+    alt!(
+        register |
+        float_register |
+        operand
     )
 );
 
-named!(pub instruction_two<CompleteStr, AssemblerInstruction>,
-    do_parse!(
-        o: opcode >>
-        opt!(multispace) >>
-        (
-            AssemblerInstruction {
-                label: None,
-                opcode: o,
-                operand1: None,
-                operand2: None,
-                operand3: None
+/// Parses an instruction, optionally preceded by a label declaration:
+/// loop: add $0 $1 $2
+/// Parsing stops at the first thing that is not an operand. Whether anything may follow is up to
+/// the caller.
+pub fn instruction(input: CompleteStr) -> Result<(CompleteStr, AssemblerInstruction), LineError> {
+    let (input, label) = match label_declaration(input) {
+        Ok((rest, label)) => (rest, Some(label)),
+        Err(_) => (input, None),
+    };
+    let start = input.0.trim_start();
+    let (mut rest, code) = match opcode(CompleteStr(start)) {
+        Ok((rest, Token::Op { code })) => (rest, code),
+        _ => return Err(LineError::unexpected(start, "instruction")),
+    };
+    if code == Opcode::IGL {
+        let mnemonic = &start[..start.len() - rest.len()];
+        return Err(LineError::new(
+            start,
+            AssemblerErrorKind::UnknownInstruction(mnemonic.to_string()),
+        ));
+    }
+
+    let mut operands = vec![];
+    loop {
+        let at = rest.0.trim_start();
+        match instruction_operand(CompleteStr(at)) {
+            Ok((remaining, token)) => {
+                operands.push((at, token));
+                rest = remaining;
             }
-        )
-    )
-);
+            Err(_) => {
+                rest = CompleteStr(at);
+                break;
+            }
+        }
+    }
+    let encoding = code.encoding();
+    if let Some(e) = malformed_operand(rest.0, encoding.operands.get(operands.len())) {
+        return Err(e);
+    }
+    check_operands(encoding.operands, &operands, rest.0)?;
+
+    let operands = operands.into_iter().map(|(_, token)| token).collect();
+    Ok((rest, AssemblerInstruction::new(label, code, operands)))
+}
 
-named!(pub instruction_three<CompleteStr, AssemblerInstruction>,
-    do_parse!(
-        o: opcode >>
-        r: register >>
-        i: register >>
-        j: register >>
-        (
-            AssemblerInstruction {
-                label: None,
-                opcode: o,
-                operand1: Some(r),
-                operand2: Some(i),
-                operand3: Some(j)
+/// More specific error for text that starts like an operand but is not a valid one. `expected` is
+/// the operand the opcode takes at this position, if any.
+fn malformed_operand<'a>(at: &'a str, expected: Option<&OperandKind>) -> Option<LineError<'a>> {
+    let word = first_word(at);
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let is_integer = |s: &str| scan_integer(s).map(|(len, _)| len) == Some(s.len());
+    let kind = match word.chars().next()? {
+        '$' if is_number(word[1..].strip_prefix('f').unwrap_or(&word[1..])) => {
+            AssemblerErrorKind::RegisterOutOfRange(word.to_string())
+        }
+        '#' if is_integer(&word[1..]) => {
+            let (min, max) = expected.unwrap_or(&OperandKind::Integer).range();
+            AssemblerErrorKind::IntegerOutOfRange {
+                text: word.to_string(),
+                min,
+                max,
             }
-        )
-    )
-);
+        }
+        '$' | '#' | '@' => return Some(LineError::unexpected(at, "operand")),
+        _ => return None,
+    };
+    Some(LineError::new(at, kind))
+}
 
-named!(pub instruction<CompleteStr, AssemblerInstruction>,
-    do_parse!(
-        ins: alt!(
-            instruction_one |
-            instruction_two |
-            instruction_three
-        ) >>
-        (
-            ins
-        )
-    )
-);
+/// Checks the operands against the kinds the opcode's encoding expects, and their values against
+/// the range each kind can hold.
+fn check_operands<'a>(
+    expected: &[OperandKind],
+    operands: &[(&'a str, Token)],
+    end: &'a str,
+) -> Result<(), LineError<'a>> {
+    for (index, kind) in expected.iter().enumerate() {
+        let (at, token) = match operands.get(index) {
+            Some(operand) => operand,
+            None => return Err(LineError::unexpected(end, kind.describe())),
+        };
+        if !token.is_operand_of_kind(*kind) {
+            return Err(LineError::new(
+                at,
+                AssemblerErrorKind::Unexpected {
+                    expected: kind.describe().to_string(),
+                    found: token.describe().to_string(),
+                },
+            ));
+        }
+
+        let (min, max) = kind.range();
+        let value = match token {
+            Token::Register { reg_num } | Token::FloatRegister { reg_num } => i64::from(*reg_num),
+            Token::IntegerOperand { value } => i64::from(*value),
+            _ => continue,
+        };
+        if value < min || value > max {
+            let text = first_word(at).to_string();
+            let error = match kind {
+                OperandKind::Register | OperandKind::FloatRegister => {
+                    AssemblerErrorKind::RegisterOutOfRange(text)
+                }
+                _ => AssemblerErrorKind::IntegerOutOfRange { text, min, max },
+            };
+            return Err(LineError::new(at, error));
+        }
+    }
+
+    match operands.get(expected.len()) {
+        Some((at, token)) => Err(LineError::new(
+            at,
+            AssemblerErrorKind::Unexpected {
+                expected: "end of line".to_string(),
+                found: token.describe().to_string(),
+            },
+        )),
+        None => Ok(()),
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::instructions::Opcode;
+    use crate::assembler::symbols::Section;
 
     use super::*;
 
     #[test]
     fn test_parse_instruction_form_one() {
-        let result = instruction_one(CompleteStr("load $0 #100\n"));
+        let result = instruction(CompleteStr("load $0 #100\n"));
         assert_eq!(
             result,
             Ok((
@@ -158,7 +275,7 @@ mod tests {
 
     #[test]
     fn test_parse_instruction_form_two() {
-        let result = instruction_two(CompleteStr("hlt\n"));
+        let result = instruction(CompleteStr("hlt\n"));
         assert_eq!(
             result,
             Ok((
@@ -173,4 +290,179 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn test_parse_instruction_form_five() {
+        let result = instruction(CompleteStr("push $3\n"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                AssemblerInstruction {
+                    label: None,
+                    opcode: Token::Op { code: Opcode::PUSH },
+                    operand1: Some(Token::Register { reg_num: 3 }),
+                    operand2: None,
+                    operand3: None,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction_form_six() {
+        let result = instruction(CompleteStr("call #12"));
+        assert_eq!(
+            result.unwrap().1.to_bytes(&SymbolTable::new()),
+            vec![Opcode::CALL as u8, 0, 12]
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction_form_three() {
+        let result = instruction(CompleteStr("add $0 $1 $2\n"));
+        assert_eq!(
+            result.unwrap().1.to_bytes(&SymbolTable::new()),
+            vec![1, 0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction_form_four() {
+        let result = instruction(CompleteStr("loadw $4 $2\n"));
+        assert_eq!(
+            result.unwrap().1.to_bytes(&SymbolTable::new()),
+            vec![Opcode::LOADW as u8, 4, 2]
+        );
+    }
+
+    #[test]
+    fn test_parse_loadi() {
+        let (_, loadi) = instruction(CompleteStr("loadi $2 #-70000")).unwrap();
+        assert_eq!(loadi.encoded_len(), 6);
+        assert_eq!(
+            loadi.to_bytes(&SymbolTable::new()),
+            vec![Opcode::LOADI as u8, 2, 255, 254, 238, 144]
+        );
+
+        let (_, load) = instruction(CompleteStr("load $0 #0xFFFF")).unwrap();
+        assert_eq!(load.to_bytes(&SymbolTable::new()), vec![0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_parse_float_instructions() {
+        let (_, loadf) = instruction(CompleteStr("loadf $f1 #-2.5")).unwrap();
+        let mut expected = vec![Opcode::LOADF as u8, 1];
+        expected.extend_from_slice(&(-2.5f64).to_be_bytes());
+        assert_eq!(loadf.to_bytes(&SymbolTable::new()), expected);
+
+        let (_, loadf) = instruction(CompleteStr("loadf $f0 #3")).unwrap();
+        assert_eq!(loadf.to_bytes(&SymbolTable::new())[2..], 3f64.to_be_bytes());
+
+        let (_, fadd) = instruction(CompleteStr("fadd $f0 $f1 $f2")).unwrap();
+        assert_eq!(fadd.to_bytes(&SymbolTable::new()), vec![50, 0, 1, 2]);
+        let (_, ftoi) = instruction(CompleteStr("ftoi $f3 $4")).unwrap();
+        assert_eq!(ftoi.to_bytes(&SymbolTable::new()), vec![61, 3, 4]);
+
+        let e = instruction(CompleteStr("fadd $f0 $1 $f2")).unwrap_err();
+        assert_eq!(
+            e.kind.to_string(),
+            "expected float register, found register"
+        );
+        let e = instruction(CompleteStr("load $0 #1.5")).unwrap_err();
+        assert_eq!(
+            e.kind.to_string(),
+            "expected integer operand, found float operand"
+        );
+        let e = instruction(CompleteStr("itof $0 $f32")).unwrap_err();
+        assert_eq!(
+            e.kind,
+            AssemblerErrorKind::RegisterOutOfRange("$f32".to_string())
+        );
+        let e = instruction(CompleteStr("loadf $f999 #1.0")).unwrap_err();
+        assert_eq!(
+            e.kind,
+            AssemblerErrorKind::RegisterOutOfRange("$f999".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction_with_label() {
+        let result = instruction(CompleteStr("test: load $0 @test\n"));
+        let (_, instruction) = result.unwrap();
+        assert_eq!(instruction.label(), Some("test"));
+        assert_eq!(instruction.label_usages(), vec!["test"]);
+        assert_eq!(instruction.encoded_len(), 4);
+
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol("test", 300, Section::Code);
+        assert_eq!(instruction.to_bytes(&symbols), vec![0, 0, 1, 44]);
+    }
+
+    #[test]
+    fn test_parse_instruction_errors() {
+        let input = "add $0 #1 $2";
+        let e = instruction(CompleteStr(input)).unwrap_err();
+        assert_eq!(e.at, &input[7..]);
+        assert_eq!(e.len, 2);
+        assert_eq!(
+            e.kind.to_string(),
+            "expected register, found integer operand"
+        );
+
+        let e = instruction(CompleteStr("load $999 #1")).unwrap_err();
+        assert_eq!(
+            e.kind,
+            AssemblerErrorKind::RegisterOutOfRange("$999".to_string())
+        );
+        let e = instruction(CompleteStr("load $32 #1")).unwrap_err();
+        assert_eq!(
+            e.kind,
+            AssemblerErrorKind::RegisterOutOfRange("$32".to_string())
+        );
+        let e = instruction(CompleteStr("load $1 #99999999999")).unwrap_err();
+        assert_eq!(
+            e.kind.to_string(),
+            "integer `#99999999999` is out of range (0 to 65535)"
+        );
+        let e = instruction(CompleteStr("load $1 #70000")).unwrap_err();
+        assert_eq!(
+            e.kind.to_string(),
+            "integer `#70000` is out of range (0 to 65535)"
+        );
+        let e = instruction(CompleteStr("call #-1")).unwrap_err();
+        assert_eq!(e.at, "#-1");
+        let e = instruction(CompleteStr("fly $1")).unwrap_err();
+        assert_eq!(
+            e.kind,
+            AssemblerErrorKind::UnknownInstruction("fly".to_string())
+        );
+        let e = instruction(CompleteStr("add $0 $1 $2 $3")).unwrap_err();
+        assert_eq!(e.kind.to_string(), "expected end of line, found register");
+        let e = instruction(CompleteStr("hlt $1")).unwrap_err();
+        assert_eq!(e.kind.to_string(), "expected end of line, found register");
+        let input = "add $0 $1";
+        let e = instruction(CompleteStr(input)).unwrap_err();
+        assert_eq!(e.at, &input[9..]);
+        assert_eq!(e.kind.to_string(), "expected register, found end of line");
+        let e = instruction(CompleteStr("loadi $1 #99999999999")).unwrap_err();
+        assert_eq!(
+            e.kind.to_string(),
+            "integer `#99999999999` is out of range (-2147483648 to 2147483647)"
+        );
+    }
+
+    #[test]
+    fn test_display_instruction() {
+        let instruction = AssemblerInstruction::new(
+            Some("start".to_string()),
+            Opcode::LOAD,
+            vec![Token::register(2), Token::label_usage("end".to_string())],
+        );
+        assert_eq!(instruction.to_string(), "start: load $2 @end");
+        assert_eq!(
+            AssemblerInstruction::new(None, Opcode::HLT, vec![]).to_string(),
+            "hlt"
+        );
+    }
 }
@@ -0,0 +1,69 @@
+use nom::types::CompleteStr;
+use nom::*;
+
+use crate::assembler::Token;
+
+fn is_label_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Parser for label declarations, which end with `:` and mark the next instruction:
+// loop:
+named!(pub label_declaration<CompleteStr, String>,
+    do_parse!(
+        name: take_while1!(is_label_char) >>
+        tag!(":") >>
+        opt!(multispace) >>
+        (
+            name.to_string()
+        )
+    )
+);
+
+// Parser for label usages, which we preface with `@` in our assembly language:
+// @loop
+named!(pub label_usage<CompleteStr, Token>,
+    ws!(
+        do_parse!(
+            tag!("@") >>
+            name: take_while1!(is_label_char) >>
+            (
+                Token::label_usage(name.to_string())
+            )
+        )
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_label_declaration() {
+        let result = label_declaration(CompleteStr("fib_loop: add $0 $1 $2"));
+        assert_eq!(
+            result,
+            Ok((CompleteStr("add $0 $1 $2"), "fib_loop".to_string()))
+        );
+
+        let result = label_declaration(CompleteStr("fib_loop"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_label_usage() {
+        let result = label_usage(CompleteStr("@test"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                Token::LabelUsage {
+                    name: "test".to_string()
+                }
+            ))
+        );
+
+        let result = label_usage(CompleteStr("test"));
+        assert!(result.is_err());
+    }
+}
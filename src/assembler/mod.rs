@@ -1,16 +1,36 @@
-use crate::instructions::Opcode;
+use std::fmt;
 
+use crate::assembler::comment_parser::strip_comments;
+use crate::assembler::directive_parser::Directive;
+use crate::assembler::program_parser::{program, Program, Statement};
+use crate::instructions::{Opcode, OperandKind};
+
+pub use self::error::{AssemblerError, AssemblerErrorKind};
+pub use self::symbols::{Section, SymbolTable};
+
+pub mod comment_parser;
+pub mod directive_parser;
+mod error;
 pub mod instruction_parser;
+pub mod label_parser;
 pub mod opcode_parser;
 pub mod operand_parser;
 pub mod program_parser;
 pub mod register_parser;
+pub mod symbols;
+
+/// Largest size of the code and of the data section, 16 MiB. Keeps both, and the bytecode file
+/// holding them, well within the u32 offsets and lengths of the file header.
+pub const MAX_SECTION_LEN: u32 = 16 * 1024 * 1024;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Op { code: Opcode },
     Register { reg_num: u8 },
     IntegerOperand { value: i32 },
+    LabelUsage { name: String },
+    FloatRegister { reg_num: u8 },
+    FloatOperand { value: f64 },
 }
 
 impl Token {
@@ -25,4 +45,439 @@ impl Token {
     pub fn register(reg_num: u8) -> Self {
         Token::Register { reg_num }
     }
+
+    pub fn label_usage(name: String) -> Self {
+        Token::LabelUsage { name }
+    }
+
+    pub fn float_register(reg_num: u8) -> Self {
+        Token::FloatRegister { reg_num }
+    }
+
+    pub fn float_operand(value: f64) -> Self {
+        Token::FloatOperand { value }
+    }
+
+    /// True if the token can be written where an operand of `kind` is expected.
+    pub fn is_operand_of_kind(&self, kind: OperandKind) -> bool {
+        match self {
+            Token::Op { .. } => false,
+            Token::Register { .. } => kind == OperandKind::Register,
+            Token::FloatRegister { .. } => kind == OperandKind::FloatRegister,
+            Token::IntegerOperand { .. } => {
+                kind == OperandKind::Integer
+                    || kind == OperandKind::Word
+                    || kind == OperandKind::Float
+            }
+            Token::LabelUsage { .. } => kind == OperandKind::Integer || kind == OperandKind::Word,
+            Token::FloatOperand { .. } => kind == OperandKind::Float,
+        }
+    }
+
+    /// How the token is described in assembler error messages.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Token::Op { .. } => "instruction",
+            Token::Register { .. } => "register",
+            Token::IntegerOperand { .. } => "integer operand",
+            Token::LabelUsage { .. } => "label",
+            Token::FloatRegister { .. } => "float register",
+            Token::FloatOperand { .. } => "float operand",
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Op { code } => write!(f, "{}", code.mnemonic()),
+            Token::Register { reg_num } => write!(f, "${}", reg_num),
+            Token::IntegerOperand { value } => write!(f, "#{}", value),
+            Token::LabelUsage { name } => write!(f, "@{}", name),
+            Token::FloatRegister { reg_num } => write!(f, "$f{}", reg_num),
+            // Debug keeps the `.0` of whole numbers, so the text parses back as a float
+            Token::FloatOperand { value } => write!(f, "#{:?}", value),
+        }
+    }
+}
+
+/// Output of the assembler: the instruction stream and the read-only data segment it addresses.
+#[derive(Debug, Default, PartialEq)]
+pub struct AssembledProgram {
+    pub code: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// Two-pass assembler. The first pass records the byte offset of every label, the second encodes
+/// the instructions with `@label` references replaced by those offsets.
+#[derive(Debug, Default)]
+pub struct Assembler {
+    symbols: SymbolTable,
+
+    //Offset in the program the code is placed at, added to every code label
+    code_offset: u32,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Assembler::with_code_offset(0)
+    }
+
+    /// Assembler for code that will be appended to a program already `offset` bytes long, such as
+    /// a line typed into the REPL. Code labels resolve to their offset in the whole program.
+    pub fn with_code_offset(offset: u32) -> Self {
+        Assembler {
+            symbols: SymbolTable::new(),
+            code_offset: offset,
+        }
+    }
+
+    /// Assembles `raw`, reporting every error found rather than stopping at the first. Lines that
+    /// do not parse are reported on their own, since later checks would mostly repeat them.
+    pub fn assemble(&mut self, raw: &str) -> Result<AssembledProgram, Vec<AssemblerError>> {
+        self.assemble_sections(raw, true)
+    }
+
+    /// Assembles a program without a `.data` section and returns its code, for running it without
+    /// a bytecode file. A `.data` directive is reported as an error.
+    pub fn assemble_code(&mut self, raw: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        self.assemble_sections(raw, false)
+            .map(|program| program.code)
+    }
+
+    fn assemble_sections(
+        &mut self,
+        raw: &str,
+        allow_data: bool,
+    ) -> Result<AssembledProgram, Vec<AssemblerError>> {
+        let program = program(raw)?;
+        let source = Source::new(raw);
+
+        self.symbols = SymbolTable::new();
+        let mut errors = vec![];
+        self.collect_symbols(&source, &program, allow_data, &mut errors);
+        self.check_label_usages(&source, &program, &mut errors);
+        if !errors.is_empty() {
+            errors.sort_by_key(|e| (e.line, e.column));
+            return Err(errors);
+        }
+        Ok(program.to_bytes(&self.symbols))
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// First pass. Statements before any section directive belong to the code section.
+    fn collect_symbols(
+        &mut self,
+        source: &Source,
+        program: &Program,
+        allow_data: bool,
+        errors: &mut Vec<AssemblerError>,
+    ) {
+        let mut section = Section::Code;
+        let mut code_offset = self.code_offset;
+        let mut data_offset = 0;
+        for statement in program.statements() {
+            let error = |kind| source.error(statement.line, kind, None);
+            let (label, len) = match &statement.statement {
+                Statement::Instruction(instruction) => {
+                    if section != Section::Code {
+                        errors.push(error(AssemblerErrorKind::InstructionInDataSection));
+                    }
+                    (instruction.label(), instruction.encoded_len())
+                }
+                Statement::Directive(directive) => {
+                    match directive.directive() {
+                        Directive::Code => section = Section::Code,
+                        Directive::Data if !allow_data => {
+                            errors.push(error(AssemblerErrorKind::DataSectionNotAllowed));
+                        }
+                        Directive::Data => section = Section::Data,
+                        Directive::Byte(_) => {}
+                        _ if section != Section::Data => {
+                            errors.push(error(AssemblerErrorKind::DataOutsideDataSection));
+                        }
+                        _ => {}
+                    }
+                    (directive.label(), directive.encoded_len())
+                }
+                Statement::Label(name) => (Some(name.as_str()), 0),
+            };
+
+            let offset = match section {
+                Section::Code => &mut code_offset,
+                Section::Data => &mut data_offset,
+            };
+            if let Some(name) = label {
+                let declaration = format!("{}:", name);
+                let error = |kind| source.error(statement.line, kind, Some(&declaration));
+                if *offset > u32::from(u16::MAX) {
+                    errors.push(error(AssemblerErrorKind::LabelOutOfRange(name.to_string())));
+                } else if !self.symbols.add_symbol(name, *offset, section) {
+                    errors.push(error(AssemblerErrorKind::DuplicateLabel(name.to_string())));
+                }
+            }
+            match offset.checked_add(len as u32) {
+                Some(end) if len <= MAX_SECTION_LEN as usize && end <= MAX_SECTION_LEN => {
+                    *offset = end
+                }
+                _ => errors.push(error(AssemblerErrorKind::SectionTooLarge(section))),
+            }
+        }
+    }
+
+    fn check_label_usages(
+        &self,
+        source: &Source,
+        program: &Program,
+        errors: &mut Vec<AssemblerError>,
+    ) {
+        for statement in program.statements() {
+            let usages = match &statement.statement {
+                Statement::Instruction(instruction) => instruction.label_usages(),
+                Statement::Directive(directive) => directive.label_usages(),
+                Statement::Label(_) => vec![],
+            };
+            for name in usages {
+                if self.symbols.symbol_value(name).is_none() {
+                    errors.push(source.error(
+                        statement.line,
+                        AssemblerErrorKind::UnknownLabel(name.to_string()),
+                        Some(&format!("@{}", name)),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Source text as written, and with comments blanked out for finding things in it.
+struct Source<'a> {
+    raw: &'a str,
+    code: String,
+}
+
+impl<'a> Source<'a> {
+    fn new(raw: &'a str) -> Self {
+        Source {
+            raw,
+            code: strip_comments(raw).0,
+        }
+    }
+
+    /// Error for the statement on line `line`, pointing at the first occurrence of `needle` or,
+    /// without one, at the whole statement.
+    fn error(&self, line: usize, kind: AssemblerErrorKind, needle: Option<&str>) -> AssemblerError {
+        let text = self.code.lines().nth(line - 1).unwrap_or("");
+        let snippet = self.raw.lines().nth(line - 1).unwrap_or("");
+        let (offset, len) = match needle.and_then(|needle| text.find(needle)) {
+            Some(offset) => (offset, needle.map_or(0, str::len)),
+            None => (text.len() - text.trim_start().len(), text.trim().len()),
+        };
+        AssemblerError::new(kind, line, snippet, offset, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_code() {
+        let code = Assembler::new().assemble_code("load $0 #1\nhlt\n").unwrap();
+        assert_eq!(code, vec![0, 0, 0, 1, 6]);
+
+        let errors = Assembler::new()
+            .assemble_code("hlt\n  .data\nmsg: .asciiz \"hi\"\n")
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, AssemblerErrorKind::DataSectionNotAllowed);
+        assert_eq!((errors[0].line, errors[0].column), (2, 3));
+        assert_eq!(errors[1].kind, AssemblerErrorKind::DataOutsideDataSection);
+    }
+
+    #[test]
+    fn test_assemble_with_code_offset() {
+        let mut assembler = Assembler::with_code_offset(8);
+        let code = assembler.assemble_code("loop: load $0 @loop\n").unwrap();
+        assert_eq!(code, vec![0, 0, 0, 8]);
+        assert_eq!(assembler.symbols().symbol_value("loop"), Some(8));
+    }
+
+    #[test]
+    fn test_assemble_forward_and_backward_labels() {
+        let mut assembler = Assembler::new();
+        let source = "load $0 #3\nload $1 @end\nstart: eq $0 $2\njmpe $1\ncall @start\nend: hlt\n";
+        let bytes = assembler.assemble(source).unwrap().code;
+        assert_eq!(assembler.symbols().symbol_value("start"), Some(8));
+        assert_eq!(assembler.symbols().symbol_value("end"), Some(17));
+        assert_eq!(
+            bytes,
+            vec![0, 0, 0, 3, 0, 1, 0, 17, 9, 0, 2, 0, 15, 1, 20, 0, 8, 6]
+        );
+    }
+
+    #[test]
+    fn test_assemble_label_on_own_line() {
+        let mut assembler = Assembler::new();
+        let bytes = assembler
+            .assemble("loop:\n  jmpf $0\ncall @loop\n")
+            .unwrap()
+            .code;
+        assert_eq!(bytes, vec![7, 0, 20, 0, 0]);
+    }
+
+    #[test]
+    fn test_assemble_unknown_label() {
+        let mut assembler = Assembler::new();
+        let e = assembler
+            .assemble("hlt\ncall @nowhere\n")
+            .unwrap_err()
+            .remove(0);
+        assert_eq!(
+            e.kind,
+            AssemblerErrorKind::UnknownLabel("nowhere".to_string())
+        );
+        assert_eq!((e.line, e.column, e.len), (2, 6, 8));
+        assert_eq!(
+            e.to_string(),
+            "error: unknown label `nowhere`\n --> 2:6\n  |\n2 | call @nowhere\n  |      ^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_assemble_duplicate_label() {
+        let mut assembler = Assembler::new();
+        let e = assembler
+            .assemble("a: hlt\na: hlt\n")
+            .unwrap_err()
+            .remove(0);
+        assert_eq!(e.kind, AssemblerErrorKind::DuplicateLabel("a".to_string()));
+        assert_eq!((e.line, e.column, e.len), (2, 1, 2));
+    }
+
+    #[test]
+    fn test_assemble_data_section() {
+        let mut assembler = Assembler::new();
+        let source = "load $0 @msg\nloadcb $1 $0\nhlt\n.data\ntable: .word @done\nmsg: .asciiz \"ok\"\n.space 2\n.code\ndone: hlt\n";
+        let program = assembler.assemble(source).unwrap();
+        assert_eq!(
+            assembler.symbols().symbol("msg").unwrap().section,
+            Section::Data
+        );
+        assert_eq!(program.code, vec![0, 0, 0, 4, 28, 1, 0, 6, 6]);
+        assert_eq!(program.data, vec![0, 0, 0, 8, b'o', b'k', 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_assemble_misplaced_statements() {
+        let mut assembler = Assembler::new();
+        let e = assembler.assemble(".data\nhlt\n").unwrap_err().remove(0);
+        assert_eq!(e.kind, AssemblerErrorKind::InstructionInDataSection);
+        assert_eq!(e.line, 2);
+        let e = assembler
+            .assemble("hlt\n  .word 1\n")
+            .unwrap_err()
+            .remove(0);
+        assert_eq!(e.kind, AssemblerErrorKind::DataOutsideDataSection);
+        assert_eq!((e.line, e.column, e.len), (2, 3, 7));
+    }
+
+    #[test]
+    fn test_assemble_byte_in_either_section() {
+        let program = Assembler::new()
+            .assemble("hlt\n.byte 200\n.data\n.byte 7\n.code\nhlt\n")
+            .unwrap();
+        assert_eq!(program.code, vec![6, 200, 6]);
+        assert_eq!(program.data, vec![7]);
+    }
+
+    #[test]
+    fn test_assemble_comment_markers_in_characters() {
+        let program = Assembler::new()
+            .assemble("load $0 #';'\nload $1 #'\"' ; quote\n")
+            .unwrap();
+        assert_eq!(program.code, vec![0, 0, 0, 59, 0, 1, 0, 34]);
+    }
+
+    #[test]
+    fn test_assemble_section_too_large() {
+        let errors = Assembler::new()
+            .assemble(".data\n.space 4294967295\n.byte 1\n")
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert!(matches!(
+            errors[0].kind,
+            AssemblerErrorKind::IntegerOutOfRange { .. }
+        ));
+
+        let errors = Assembler::new()
+            .assemble(".data\n.space 16777216\n.byte 1\n")
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].kind,
+            AssemblerErrorKind::SectionTooLarge(Section::Data)
+        );
+        assert_eq!((errors[0].line, errors[0].column), (3, 1));
+        assert_eq!(
+            errors[0].kind.to_string(),
+            "the .data section grows past 16777216 bytes"
+        );
+    }
+
+    #[test]
+    fn test_assemble_reports_bad_operands() {
+        let mut assembler = Assembler::new();
+        let e = assembler
+            .assemble("load $1 #2\n\tadd $0 #1 $2\n")
+            .unwrap_err()
+            .remove(0);
+        assert_eq!(
+            e.to_string(),
+            "error: expected register, found integer operand\n --> 2:9\n  |\n2 | \tadd $0 #1 $2\n  | \t       ^^"
+        );
+        let e = assembler.assemble("push $999\n").unwrap_err().remove(0);
+        assert_eq!(
+            e.kind,
+            AssemblerErrorKind::RegisterOutOfRange("$999".to_string())
+        );
+        assert_eq!((e.line, e.column, e.len), (1, 6, 4));
+    }
+
+    #[test]
+    fn test_assemble_with_comments() {
+        let mut assembler = Assembler::new();
+        let source = "; entry point\nmain: /* start */ load $0 @end ; forward\n\n  end: hlt\n";
+        let program = assembler.assemble(source).unwrap();
+        assert_eq!(program.code, vec![0, 0, 0, 4, 6]);
+
+        let e = assembler
+            .assemble("/* @nowhere */ call @nowhere ; @nowhere\n")
+            .unwrap_err()
+            .remove(0);
+        assert_eq!((e.column, e.len), (21, 8));
+    }
+
+    #[test]
+    fn test_assemble_reports_every_error() {
+        let mut assembler = Assembler::new();
+        let source = "a: call @b\na: hlt\n.data\nhlt\ncall @c\n";
+        let errors = assembler.assemble(source).unwrap_err();
+        let found: Vec<(usize, AssemblerErrorKind)> =
+            errors.into_iter().map(|e| (e.line, e.kind)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, AssemblerErrorKind::UnknownLabel("b".to_string())),
+                (2, AssemblerErrorKind::DuplicateLabel("a".to_string())),
+                (4, AssemblerErrorKind::InstructionInDataSection),
+                (5, AssemblerErrorKind::InstructionInDataSection),
+                (5, AssemblerErrorKind::UnknownLabel("c".to_string())),
+            ]
+        );
+    }
 }
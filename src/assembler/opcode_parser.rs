@@ -22,16 +22,16 @@ mod tests {
     #[test]
     fn test_parse_register() {
         let result = opcode(CompleteStr("load"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         assert_eq!(result.unwrap().1, Token::Op { code: Opcode::LOAD });
 
         let result = opcode(CompleteStr(""));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
 
         let result = opcode(CompleteStr("$a"));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
 
         let result = opcode(CompleteStr("0"));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
     }
 }
@@ -1,33 +1,235 @@
-use nom::digit;
+use std::convert::TryFrom;
+
 use nom::types::CompleteStr;
 use nom::*;
 
+use crate::assembler::label_parser::label_usage;
 use crate::assembler::Token;
 
-/// Parser for integer numbers, which we preface with `#` in our assembly language:
-/// #100
+/// Scans an integer literal at the start of `input`: an optional `-` followed by decimal digits,
+/// `0x` hex digits or `0b` binary digits, with `_` allowed between digits, or a character in
+/// single quotes such as `'A'` or `'\n'`. Returns the length of the literal and its value, which is
+/// `None` when it does not fit in an `i64`.
+pub fn scan_integer(input: &str) -> Option<(usize, Option<i64>)> {
+    if let Some(rest) = input.strip_prefix('\'') {
+        let mut chars = rest.chars();
+        let value = match chars.next()? {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                '0' => '\0',
+                '\\' => '\\',
+                '\'' => '\'',
+                _ => return None,
+            },
+            '\'' => return None,
+            c => c,
+        };
+        if chars.next()? != '\'' {
+            return None;
+        }
+        let len = input.len() - chars.as_str().len();
+        return Some((len, Some(i64::from(u32::from(value)))));
+    }
+
+    let (negative, unsigned) = match input.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, input),
+    };
+    let (radix, digits) = if let Some(rest) = unsigned.strip_prefix("0x") {
+        (16, rest)
+    } else if let Some(rest) = unsigned.strip_prefix("0b") {
+        (2, rest)
+    } else {
+        (10, unsigned)
+    };
+    let len = digits
+        .find(|c: char| !c.is_digit(radix) && c != '_')
+        .unwrap_or(digits.len());
+    let text = &digits[..len];
+    if !text.starts_with(|c: char| c.is_digit(radix)) {
+        return None;
+    }
+    let cleaned: String = text.chars().filter(|&c| c != '_').collect();
+    let value =
+        i64::from_str_radix(&cleaned, radix)
+            .ok()
+            .map(|value| if negative { -value } else { value });
+    Some((input.len() - digits.len() + len, value))
+}
+
+/// Integer literal, as described by `scan_integer`, that fits in an `i32`.
+pub fn integer_literal(input: CompleteStr) -> IResult<CompleteStr, i32> {
+    match scan_integer(&input) {
+        Some((len, Some(value))) => match i32::try_from(value) {
+            Ok(value) => Ok((CompleteStr(&input[len..]), value)),
+            Err(_) => Err(Err::Error(error_position!(input, ErrorKind::Custom(0)))),
+        },
+        _ => Err(Err::Error(error_position!(input, ErrorKind::Custom(0)))),
+    }
+}
+
+/// Length of the float literal at the start of `input`: an optional `-`, decimal digits, and a
+/// fraction such as `.5`, an exponent such as `e-3`, or both. Integers are not float literals.
+pub fn scan_float(input: &str) -> Option<usize> {
+    let digits = |from: usize| {
+        input[from..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(input.len() - from)
+    };
+    let mut len = usize::from(input.starts_with('-'));
+    let whole = digits(len);
+    if whole == 0 {
+        return None;
+    }
+    len += whole;
+    let mut is_float = false;
+    if input[len..].starts_with('.') && digits(len + 1) > 0 {
+        len += 1 + digits(len + 1);
+        is_float = true;
+    }
+    if input[len..].starts_with(['e', 'E']) {
+        let sign = usize::from(input[len + 1..].starts_with(['+', '-']));
+        let exponent = digits(len + 1 + sign);
+        if exponent > 0 {
+            len += 1 + sign + exponent;
+            is_float = true;
+        }
+    }
+    if is_float {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Float literal, as described by `scan_float`.
+pub fn float_literal(input: CompleteStr) -> IResult<CompleteStr, f64> {
+    match scan_float(&input).map(|len| (len, input[..len].parse())) {
+        Some((len, Ok(value))) => Ok((CompleteStr(&input[len..]), value)),
+        _ => Err(Err::Error(error_position!(input, ErrorKind::Custom(0)))),
+    }
+}
+
+// Parser for float numbers, written like integers with a fraction or exponent:
+// #3.14 #-0.5 #6.02e23
+named!(pub float_operand<CompleteStr, Token>,
+    ws!(
+        do_parse!(
+            tag!("#") >>
+            value: float_literal >>
+            (
+                Token::float_operand(value)
+            )
+        )
+    )
+);
+
+// Parser for integer numbers, which we preface with `#` in our assembly language:
+// #100 #-5 #0xFF #0b1010 #'A' #1_000_000
 named!(pub integer_operand<CompleteStr, Token>,
     ws!(
         do_parse!(
             tag!("#") >>
-            reg_num: digit >>
+            value: integer_literal >>
             (
-                Token::operand(reg_num.parse::<i32>().unwrap())
+                Token::operand(value)
             )
         )
     )
 );
 
+// Parser for operands that end up as immediates: a float, an integer or a label usage
+named!(pub operand<CompleteStr, Token>,
+    alt!(
+        float_operand |
+        integer_operand |
+        label_usage
+    )
+);
+
+#[test]
+fn test_parse_operand() {
+    let result = operand(CompleteStr("@loop"));
+    assert_eq!(result.unwrap().1, Token::label_usage("loop".to_string()));
+    let result = operand(CompleteStr("#7"));
+    assert_eq!(result.unwrap().1, Token::operand(7));
+}
+
 #[test]
 fn test_parse_integer_operand() {
     // Test a valid integer operand
     let result = integer_operand(CompleteStr("#10"));
-    assert_eq!(result.is_ok(), true);
+    assert!(result.is_ok());
     let (rest, value) = result.unwrap();
     assert_eq!(rest, CompleteStr(""));
     assert_eq!(value, Token::IntegerOperand { value: 10 });
 
     // Test an invalid one (missing the #)
     let result = integer_operand(CompleteStr("10"));
-    assert_eq!(result.is_ok(), false);
+    assert!(result.is_err());
+
+    // Test one that does not fit in an i32
+    let result = integer_operand(CompleteStr("#99999999999"));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_parse_integer_literals() {
+    let cases: &[(&str, i32)] = &[
+        ("#-5", -5),
+        ("#0xFF", 255),
+        ("#0xff_ff", 65535),
+        ("#0b1010", 10),
+        ("#-0b1", -1),
+        ("#'A'", 65),
+        ("#'\\n'", 10),
+        ("#1_000_000", 1_000_000),
+        ("#-2147483648", i32::MIN),
+    ];
+    for &(text, expected) in cases {
+        let result = integer_operand(CompleteStr(text));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::operand(expected))),
+            "{}",
+            text
+        );
+    }
+
+    for text in &["#0x", "#0b2", "#''", "#'ab'", "#-", "#2147483648", "#_1"] {
+        assert!(integer_operand(CompleteStr(text)).is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn test_scan_integer() {
+    assert_eq!(scan_integer("0x10 rest"), Some((4, Some(16))));
+    assert_eq!(scan_integer("99999999999999999999"), Some((20, None)));
+    assert_eq!(scan_integer("abc"), None);
+}
+
+#[test]
+fn test_parse_float_operand() {
+    let cases: &[(&str, f64)] = &[
+        ("#2.75", 2.75),
+        ("#-0.5", -0.5),
+        ("#6.02e23", 6.02e23),
+        ("#1E-3", 0.001),
+        ("#2.5e+2", 250.0),
+    ];
+    for &(text, expected) in cases {
+        let result = operand(CompleteStr(text));
+        assert_eq!(
+            result,
+            Ok((CompleteStr(""), Token::float_operand(expected))),
+            "{}",
+            text
+        );
+    }
+
+    assert_eq!(operand(CompleteStr("#3")).unwrap().1, Token::operand(3));
+    for text in &["#.5", "#1.", "#1e", "#-"] {
+        assert!(float_operand(CompleteStr(text)).is_err(), "{}", text);
+    }
 }
@@ -1,51 +1,230 @@
-use nom::*;
 use nom::types::CompleteStr;
 
-use crate::assembler::instruction_parser::{AssemblerInstruction, instruction};
+use crate::assembler::comment_parser::strip_comments;
+use crate::assembler::directive_parser::{directive, AssemblerDirective, Directive};
+use crate::assembler::error::LineError;
+use crate::assembler::instruction_parser::{instruction, AssemblerInstruction};
+use crate::assembler::label_parser::label_declaration;
+#[cfg(test)]
+use crate::assembler::AssemblerErrorKind;
+use crate::assembler::{AssembledProgram, AssemblerError, Section, SymbolTable};
+
+#[derive(Debug, PartialEq)]
+pub enum Statement {
+    Instruction(AssemblerInstruction),
+    Directive(AssemblerDirective),
+    /// A label on a line of its own. It marks whatever comes next in the current section.
+    Label(String),
+}
+
+/// A statement together with the 1-based number of the line it was written on.
+#[derive(Debug, PartialEq)]
+pub struct SourceStatement {
+    pub line: usize,
+    pub statement: Statement,
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Program {
-    instructions: Vec<AssemblerInstruction>,
+    statements: Vec<SourceStatement>,
 }
 
 impl Program {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut program = vec![];
-        for instruction in &self.instructions {
-            program.append(&mut instruction.to_bytes());
+    pub fn statements(&self) -> &[SourceStatement] {
+        &self.statements
+    }
+
+    /// Encodes instructions into the code segment and directives into the segment of the section
+    /// they are in. Sections are expected to have been checked by the assembler.
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> AssembledProgram {
+        let mut program = AssembledProgram::default();
+        let mut section = Section::Code;
+        for source in &self.statements {
+            match &source.statement {
+                Statement::Instruction(instruction) => {
+                    program.code.append(&mut instruction.to_bytes(symbols))
+                }
+                Statement::Directive(directive) => {
+                    match directive.directive() {
+                        Directive::Code => section = Section::Code,
+                        Directive::Data => section = Section::Data,
+                        _ => {}
+                    }
+                    let segment = match section {
+                        Section::Code => &mut program.code,
+                        Section::Data => &mut program.data,
+                    };
+                    segment.append(&mut directive.to_bytes(symbols))
+                }
+                Statement::Label(_) => {}
+            }
         }
-        println!("Bytes {:?}", program);
         program
     }
 }
 
-named!(pub program<CompleteStr, Program>,
-    do_parse!(
-        instructions: many1!(instruction) >>
-        (
-            Program {
-                instructions: instructions
+/// Parses `source` one line at a time once comments are removed. A line that does not parse is
+/// recorded and parsing picks up again on the next one, so every bad line is reported at once.
+pub fn program(source: &str) -> Result<Program, Vec<AssemblerError>> {
+    let (code, comment_error) = strip_comments(source);
+    let mut statements = vec![];
+    let mut errors: Vec<AssemblerError> = comment_error.into_iter().collect();
+    for (index, (line, original)) in code.lines().zip(source.lines()).enumerate() {
+        match statement(CompleteStr(line)) {
+            Ok(Some(statement)) => statements.push(SourceStatement {
+                line: index + 1,
+                statement,
+            }),
+            Ok(None) => {}
+            Err(e) => errors.push(e.at_line(index + 1, line, original)),
+        }
+    }
+    if !errors.is_empty() {
+        errors.sort_by_key(|e| (e.line, e.column));
+        return Err(errors);
+    }
+    Ok(Program { statements })
+}
+
+/// Parses a single line, which has to hold exactly one statement. Blank lines give `None`.
+pub fn statement(input: CompleteStr) -> Result<Option<Statement>, LineError> {
+    let input = input.0.trim_start();
+    if input.is_empty() {
+        return Ok(None);
+    }
+
+    let body = match label_declaration(CompleteStr(input)) {
+        Ok((rest, name)) => {
+            if rest.trim().is_empty() {
+                return Ok(Some(Statement::Label(name)));
             }
-        )
-    )
-);
+            rest.0.trim_start()
+        }
+        Err(_) => input,
+    };
+    let (rest, statement) = if body.starts_with('.') {
+        let (rest, directive) = directive(CompleteStr(input))?;
+        (rest, Statement::Directive(directive))
+    } else {
+        let (rest, instruction) = instruction(CompleteStr(input))?;
+        (rest, Statement::Instruction(instruction))
+    };
+
+    let rest = rest.0.trim_start();
+    if !rest.is_empty() {
+        return Err(LineError::unexpected(rest, "end of line"));
+    }
+    Ok(Some(statement))
+}
 
 #[test]
 fn test_parse_program() {
-    let result = program(CompleteStr("load $0 #100\n"));
-    assert_eq!(result.is_ok(), true);
-    let (leftover, p) = result.unwrap();
-    assert_eq!(leftover, CompleteStr(""));
-    assert_eq!(1, p.instructions.len());
+    let result = program("load $0 #100\n");
+    assert!(result.is_ok());
+    let p = result.unwrap();
+    assert_eq!(1, p.statements.len());
+    assert_eq!(p.statements[0].line, 1);
 }
 
 #[test]
 fn test_program_to_bytes() {
-    let result = program(CompleteStr("load $0 #100\n"));
-    assert_eq!(result.is_ok(), true);
-    let (_, program) = result.unwrap();
-    let bytecode = program.to_bytes();
-    assert_eq!(bytecode.len(), 4);
+    let result = program("load $0 #100\n");
+    assert!(result.is_ok());
+    let program = result.unwrap();
+    let bytecode = program.to_bytes(&SymbolTable::new());
+    assert_eq!(bytecode.code.len(), 4);
+    assert!(bytecode.data.is_empty());
+}
 
-    println!("{:?}", bytecode);
+#[test]
+fn test_program_with_subroutine() {
+    let result = program("call #4\nhlt\npush $0\npop $1\nret\n");
+    let program = result.unwrap();
+    assert_eq!(
+        program.to_bytes(&SymbolTable::new()).code,
+        vec![20, 0, 4, 6, 18, 0, 19, 1, 21]
+    );
+}
+
+#[test]
+fn test_program_with_data_section() {
+    let source = ".data\nmsg: .asciiz \"hi\"\n.word 7\n.code\nhlt\n";
+    let program = program(source).unwrap();
+    let bytes = program.to_bytes(&SymbolTable::new());
+    assert_eq!(bytes.code, vec![6]);
+    assert_eq!(bytes.data, vec![b'h', b'i', 0, 0, 0, 0, 7]);
+}
+
+#[test]
+fn test_program_label_on_own_line() {
+    let program = program("start:\n\nhlt\n").unwrap();
+    assert_eq!(
+        program.statements()[0].statement,
+        Statement::Label("start".to_string())
+    );
+    assert_eq!(program.statements()[1].line, 3);
+}
+
+#[test]
+fn test_program_reports_position() {
+    let e = &program("hlt\n  load $0 #1 junk\n").unwrap_err()[0];
+    assert_eq!((e.line, e.column, e.len), (2, 14, 4));
+    assert_eq!(e.snippet, "  load $0 #1 junk");
+    assert_eq!(e.kind.to_string(), "expected end of line, found `junk`");
+}
+
+#[test]
+fn test_program_reports_every_bad_line() {
+    let source = "load $0 #1\nload $0 $1 #2\nhlt\n.asciz \"x\"\nadd $0 $1 $2 trailing\nret\n";
+    let errors = program(source).unwrap_err();
+    let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+    assert_eq!(lines, vec![2, 4, 5]);
+    assert_eq!(
+        errors[2].kind.to_string(),
+        "expected end of line, found `trailing`"
+    );
+}
+
+#[test]
+fn test_program_with_comments() {
+    let source = r#"
+; Counts $0 down from 3, then exits.
+.data
+    banner: .asciiz "count; down" /* strings keep their ; */
+
+.code
+main:
+    load $0 #3          ; counter
+    load $1 #1
+    load $2 @loop       /* jump target */
+
+/*
+ * Loop body
+ */
+loop:   sub $0 $1 $0
+        eq $0 $3        ; hit zero?
+        jmpne $2
+    hlt
+"#;
+    let program = program(source).unwrap();
+    let lines: Vec<usize> = program.statements().iter().map(|s| s.line).collect();
+    assert_eq!(lines, vec![3, 4, 6, 7, 8, 9, 10, 15, 16, 17, 18]);
+
+    let mut symbols = SymbolTable::new();
+    symbols.add_symbol("loop", 12, crate::assembler::Section::Code);
+    let bytes = program.to_bytes(&symbols);
+    assert_eq!(bytes.data, b"count; down\0".to_vec());
+    assert_eq!(
+        bytes.code,
+        vec![0, 0, 0, 3, 0, 1, 0, 1, 0, 2, 0, 12, 2, 0, 1, 0, 9, 0, 3, 0, 16, 2, 6]
+    );
+}
+
+#[test]
+fn test_program_errors_point_into_commented_lines() {
+    let errors = program("/* a */ jmp $1 junk ; note\n/* open").unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!((errors[0].line, errors[0].column), (1, 16));
+    assert_eq!(errors[0].snippet, "/* a */ jmp $1 junk ; note");
+    assert_eq!(errors[1].kind, AssemblerErrorKind::UnterminatedComment);
 }
@@ -4,15 +4,29 @@ use nom::*;
 
 use crate::assembler::Token;
 
-/// Parser for integer numbers, which we preface with `#` in our assembly language:
-/// #100
+// Parser for registers, which we preface with `$` in our assembly language:
+// $0
 named!(pub register<CompleteStr, Token>,
     ws!(
         do_parse!(
             tag!("$") >>
-            reg_num: digit >>
+            reg_num: map_res!(digit, |d: CompleteStr| d.parse::<u8>()) >>
             (
-                Token::register(reg_num.parse::<u8>().unwrap())
+                Token::register(reg_num)
+            )
+        )
+    )
+);
+
+// Parser for float registers, which are prefixed with `$f`:
+// $f0
+named!(pub float_register<CompleteStr, Token>,
+    ws!(
+        do_parse!(
+            tag!("$f") >>
+            reg_num: map_res!(digit, |d: CompleteStr| d.parse::<u8>()) >>
+            (
+                Token::float_register(reg_num)
             )
         )
     )
@@ -22,12 +36,24 @@ named!(pub register<CompleteStr, Token>,
 fn test_parse_integer_operand() {
     // Test a valid integer operand
     let result = register(CompleteStr("$0"));
-    assert_eq!(result.is_ok(), true);
+    assert!(result.is_ok());
     let (rest, value) = result.unwrap();
     assert_eq!(rest, CompleteStr(""));
     assert_eq!(value, Token::Register { reg_num: 0 });
 
     // Test an invalid one (missing the #)
     let result = register(CompleteStr("10"));
-    assert_eq!(result.is_ok(), false);
+    assert!(result.is_err());
+
+    // Register numbers that overflow are rejected instead of panicking
+    let result = register(CompleteStr("$999"));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_parse_float_register() {
+    let result = float_register(CompleteStr("$f3"));
+    assert_eq!(result, Ok((CompleteStr(""), Token::float_register(3))));
+    assert!(float_register(CompleteStr("$3")).is_err());
+    assert!(register(CompleteStr("$f3")).is_err());
 }
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+/// Section a label was declared in. Code offsets count from the first instruction, data offsets
+/// from the start of the read-only data segment.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Section {
+    Code = 0,
+    Data = 1,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub offset: u32,
+    pub section: Section,
+}
+
+/// Byte offsets of the labels declared in a program, collected by the first assembler pass.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SymbolTable {
+    symbols: HashMap<String, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Records `name` at `offset`. Returns false if the name was already declared.
+    pub fn add_symbol(&mut self, name: &str, offset: u32, section: Section) -> bool {
+        if self.symbols.contains_key(name) {
+            return false;
+        }
+        self.symbols
+            .insert(name.to_string(), Symbol { offset, section });
+        true
+    }
+
+    pub fn symbol_value(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).map(|symbol| symbol.offset)
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(name)
+    }
+
+    /// All symbols ordered by section and offset, so the output is stable between runs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Symbol)> {
+        let mut symbols: Vec<_> = self
+            .symbols
+            .iter()
+            .map(|(name, symbol)| (name.as_str(), symbol))
+            .collect();
+        symbols.sort_by_key(|(name, symbol)| (symbol.section as u8, symbol.offset, *name));
+        symbols.into_iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_table() {
+        let mut symbols = SymbolTable::new();
+        assert!(symbols.add_symbol("test", 12, Section::Code));
+        assert!(!symbols.add_symbol("test", 16, Section::Data));
+        assert_eq!(symbols.symbol_value("test"), Some(12));
+        assert_eq!(symbols.symbol("test").unwrap().section, Section::Code);
+        assert_eq!(symbols.symbol_value("missing"), None);
+        assert_eq!(symbols.len(), 1);
+
+        symbols.add_symbol("data", 0, Section::Data);
+        symbols.add_symbol("start", 0, Section::Code);
+        let names: Vec<_> = symbols.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["start", "test", "data"]);
+    }
+}
@@ -0,0 +1,295 @@
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+use crate::assembler::{AssembledProgram, Section, SymbolTable};
+
+/// First bytes of every virian bytecode file.
+pub const MAGIC: [u8; 4] = *b"VIRN";
+
+/// Format version written by `BytecodeFile::to_bytes` and the only one the reader accepts.
+pub const VERSION: u16 = 1;
+
+/// Size of the fixed header that precedes the sections.
+pub const HEADER_LEN: usize = 36;
+
+/// Label the entry point is taken from when the program declares it in the code section.
+pub const ENTRY_LABEL: &str = "main";
+
+// Layout of the header, all integers big-endian like the instruction operands:
+//
+//  0  magic         4 bytes
+//  4  version       u16
+//  6  reserved      u16
+//  8  entry point   u32, offset into the code section
+// 12  code          u32 offset, u32 length
+// 20  data          u32 offset, u32 length
+// 28  symbols       u32 offset, u32 length, both zero when the table is left out
+//
+// The symbol table is a u32 count followed by one record per symbol: section (u8, 0 = code,
+// 1 = data), offset (u32), name length (u16) and the UTF-8 name.
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BytecodeError {
+    /// The input does not start with `MAGIC`.
+    BadMagic,
+    /// The file was written by a different version of the format.
+    UnsupportedVersion(u16),
+    /// The input ends before the header or a section is complete.
+    Truncated,
+    /// A section offset or length points outside of the file.
+    InvalidSection,
+    /// The symbol table is malformed.
+    InvalidSymbolTable,
+    /// The entry point lies past the end of the code section.
+    InvalidEntryPoint(u32),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::BadMagic => write!(f, "not a virian bytecode file"),
+            BytecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported bytecode version {}", version)
+            }
+            BytecodeError::Truncated => write!(f, "bytecode file is truncated"),
+            BytecodeError::InvalidSection => write!(f, "section lies outside of the file"),
+            BytecodeError::InvalidSymbolTable => write!(f, "malformed symbol table"),
+            BytecodeError::InvalidEntryPoint(entry) => {
+                write!(f, "entry point {} is outside of the code section", entry)
+            }
+        }
+    }
+}
+
+impl Error for BytecodeError {}
+
+/// Assembled program packaged with everything needed to run it on another machine.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct BytecodeFile {
+    pub entry_point: u32,
+    pub code: Vec<u8>,
+    pub data: Vec<u8>,
+    pub symbols: Option<SymbolTable>,
+}
+
+impl BytecodeFile {
+    /// Packages assembler output. The entry point is the `main` label when the code declares one,
+    /// otherwise the first instruction.
+    pub fn from_program(program: AssembledProgram, symbols: &SymbolTable) -> Self {
+        let entry_point = match symbols.symbol(ENTRY_LABEL) {
+            Some(symbol) if symbol.section == Section::Code => symbol.offset,
+            _ => 0,
+        };
+        BytecodeFile {
+            entry_point,
+            code: program.code,
+            data: program.data,
+            symbols: Some(symbols.clone()),
+        }
+    }
+
+    /// True if `bytes` starts with the magic number. Does not validate the rest of the file.
+    pub fn is_bytecode(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let symbols = self.symbols.as_ref().map(write_symbols).unwrap_or_default();
+        let code_offset = HEADER_LEN;
+        let data_offset = code_offset + self.code.len();
+        let symbols_offset = if symbols.is_empty() {
+            0
+        } else {
+            data_offset + self.data.len()
+        };
+
+        let mut results = Vec::with_capacity(data_offset + self.data.len() + symbols.len());
+        results.extend_from_slice(&MAGIC);
+        results.extend_from_slice(&VERSION.to_be_bytes());
+        results.extend_from_slice(&0u16.to_be_bytes());
+        results.extend_from_slice(&self.entry_point.to_be_bytes());
+        for &(offset, len) in &[
+            (code_offset, self.code.len()),
+            (data_offset, self.data.len()),
+            (symbols_offset, symbols.len()),
+        ] {
+            results.extend_from_slice(&(offset as u32).to_be_bytes());
+            results.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        results.extend_from_slice(&self.code);
+        results.extend_from_slice(&self.data);
+        results.extend_from_slice(&symbols);
+        results
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        if !BytecodeFile::is_bytecode(bytes) {
+            return Err(BytecodeError::BadMagic);
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(BytecodeError::Truncated);
+        }
+        let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+
+        let entry_point = read_u32(bytes, 8);
+        let code = section(bytes, 12)?.to_vec();
+        let data = section(bytes, 20)?.to_vec();
+        let symbols = match section(bytes, 28)? {
+            [] => None,
+            table => Some(read_symbols(table)?),
+        };
+        if entry_point as usize > code.len() {
+            return Err(BytecodeError::InvalidEntryPoint(entry_point));
+        }
+
+        Ok(BytecodeFile {
+            entry_point,
+            code,
+            data,
+            symbols,
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+/// Slice of the section whose offset and length are stored at `at` in the header.
+fn section(bytes: &[u8], at: usize) -> Result<&[u8], BytecodeError> {
+    let offset = read_u32(bytes, at) as usize;
+    let len = read_u32(bytes, at + 4) as usize;
+    if len == 0 {
+        return Ok(&[]);
+    }
+    match offset.checked_add(len) {
+        Some(end) if offset >= HEADER_LEN && end <= bytes.len() => Ok(&bytes[offset..end]),
+        _ => Err(BytecodeError::InvalidSection),
+    }
+}
+
+fn write_symbols(symbols: &SymbolTable) -> Vec<u8> {
+    let mut results = vec![];
+    results.extend_from_slice(&(symbols.len() as u32).to_be_bytes());
+    for (name, symbol) in symbols.iter() {
+        results.push(symbol.section as u8);
+        results.extend_from_slice(&symbol.offset.to_be_bytes());
+        results.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        results.extend_from_slice(name.as_bytes());
+    }
+    results
+}
+
+fn read_symbols(table: &[u8]) -> Result<SymbolTable, BytecodeError> {
+    let mut symbols = SymbolTable::new();
+    let mut rest = table;
+    let mut take = |len: usize| -> Result<&[u8], BytecodeError> {
+        if rest.len() < len {
+            return Err(BytecodeError::InvalidSymbolTable);
+        }
+        let (taken, remaining) = rest.split_at(len);
+        rest = remaining;
+        Ok(taken)
+    };
+
+    let count = u32::from_be_bytes(take(4)?.try_into().unwrap());
+    for _ in 0..count {
+        let section = match take(1)?[0] {
+            0 => Section::Code,
+            1 => Section::Data,
+            _ => return Err(BytecodeError::InvalidSymbolTable),
+        };
+        let offset = u32::from_be_bytes(take(4)?.try_into().unwrap());
+        let name_len = u16::from_be_bytes(take(2)?.try_into().unwrap());
+        let name = std::str::from_utf8(take(name_len as usize)?)
+            .map_err(|_| BytecodeError::InvalidSymbolTable)?;
+        if !symbols.add_symbol(name, offset, section) {
+            return Err(BytecodeError::InvalidSymbolTable);
+        }
+    }
+    if !rest.is_empty() {
+        return Err(BytecodeError::InvalidSymbolTable);
+    }
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn get_test_file() -> BytecodeFile {
+        let mut assembler = Assembler::new();
+        let source = "helper: ret\nmain: call @helper\nhlt\n.data\nmsg: .asciiz \"hi\"\n";
+        let program = assembler.assemble(source).unwrap();
+        BytecodeFile::from_program(program, assembler.symbols())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let file = get_test_file();
+        assert_eq!(file.entry_point, 1);
+        let bytes = file.to_bytes();
+        assert!(BytecodeFile::is_bytecode(&bytes));
+        assert_eq!(BytecodeFile::from_bytes(&bytes), Ok(file));
+    }
+
+    #[test]
+    fn test_round_trip_without_symbols() {
+        let file = BytecodeFile {
+            entry_point: 0,
+            code: vec![6],
+            data: vec![],
+            symbols: None,
+        };
+        let bytes = file.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 1);
+        assert_eq!(BytecodeFile::from_bytes(&bytes), Ok(file));
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        assert_eq!(
+            BytecodeFile::from_bytes(&[0, 0, 0, 6]),
+            Err(BytecodeError::BadMagic)
+        );
+        assert_eq!(BytecodeFile::from_bytes(&[]), Err(BytecodeError::BadMagic));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut bytes = get_test_file().to_bytes();
+        bytes[5] = 9;
+        assert_eq!(
+            BytecodeFile::from_bytes(&bytes),
+            Err(BytecodeError::UnsupportedVersion(9))
+        );
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let bytes = get_test_file().to_bytes();
+        assert_eq!(
+            BytecodeFile::from_bytes(&bytes[..10]),
+            Err(BytecodeError::Truncated)
+        );
+        assert_eq!(
+            BytecodeFile::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BytecodeError::InvalidSection)
+        );
+    }
+
+    #[test]
+    fn test_invalid_entry_point() {
+        let mut bytes = get_test_file().to_bytes();
+        bytes[11] = 200;
+        assert_eq!(
+            BytecodeFile::from_bytes(&bytes),
+            Err(BytecodeError::InvalidEntryPoint(200))
+        );
+    }
+}
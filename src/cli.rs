@@ -0,0 +1,474 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use virian::bytecode::{BytecodeError, BytecodeFile};
+use virian::disassembler::listing;
+use virian::verifier::{verify, Diagnostic};
+use virian::vm::{TraceFormat, TraceWriter};
+use virian::{Assembler, AssemblerError, ExitReason, VmError, VM};
+
+use crate::repl::Repl;
+
+pub const USAGE: &str = "Usage:
+    virian [repl]                      Start the interactive REPL
+    virian asm <in.s> [-o <out.vbc>]   Assemble a source file into bytecode
+    virian run <file> [--fuel <n>]     Run a bytecode or assembly file
+        [--trace <out> [--trace-format text|json]]
+                                       Write every executed instruction to <out>
+    virian disasm <file.vbc> [--bytes] Print bytecode as assembly";
+
+/// Extension given to assembled files when `asm` is not passed an output path.
+pub const BYTECODE_EXTENSION: &str = "vbc";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Repl,
+    Asm {
+        input: PathBuf,
+        output: PathBuf,
+    },
+    Run {
+        input: PathBuf,
+        fuel: Option<u64>,
+        trace: Option<PathBuf>,
+        trace_format: TraceFormat,
+    },
+    Disasm {
+        input: PathBuf,
+        show_bytes: bool,
+    },
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Usage(String),
+    Io(PathBuf, io::Error),
+    Assembler(Vec<AssemblerError>),
+    Bytecode(BytecodeError),
+    Verifier(Vec<Diagnostic>),
+    Vm(VmError),
+    OutOfFuel(u64),
+}
+
+impl CliError {
+    /// Process exit code reported for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+            CliError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            CliError::Assembler(errors) => {
+                for e in errors {
+                    write!(f, "{}\n\n", e)?;
+                }
+                match errors.len() {
+                    1 => write!(f, "aborting due to previous error"),
+                    count => write!(f, "aborting due to {} previous errors", count),
+                }
+            }
+            CliError::Bytecode(e) => write!(f, "{}", e),
+            CliError::Verifier(diagnostics) => {
+                write!(f, "program rejected by the verifier")?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {}", diagnostic)?;
+                }
+                Ok(())
+            }
+            CliError::Vm(e) => write!(f, "VM fault: {}", e),
+            CliError::OutOfFuel(fuel) => write!(f, "program ran out of fuel after {} units", fuel),
+        }
+    }
+}
+
+impl Error for CliError {}
+
+impl From<Vec<AssemblerError>> for CliError {
+    fn from(errors: Vec<AssemblerError>) -> Self {
+        CliError::Assembler(errors)
+    }
+}
+
+impl From<BytecodeError> for CliError {
+    fn from(e: BytecodeError) -> Self {
+        CliError::Bytecode(e)
+    }
+}
+
+impl From<VmError> for CliError {
+    fn from(e: VmError) -> Self {
+        CliError::Vm(e)
+    }
+}
+
+/// Parses the arguments that follow the program name.
+pub fn parse_args(args: &[String]) -> Result<Command, CliError> {
+    let (command, rest) = match args.split_first() {
+        None => return Ok(Command::Repl),
+        Some((command, rest)) => (command.as_str(), rest),
+    };
+
+    let mut inputs = vec![];
+    let mut output = None;
+    let mut show_bytes = false;
+    let mut fuel = None;
+    let mut trace = None;
+    let mut trace_format = None;
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" | "--output" if command == "asm" => match rest.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => return Err(CliError::Usage(format!("`{}` needs a file name", arg))),
+            },
+            "--bytes" if command == "disasm" => show_bytes = true,
+            "--fuel" if command == "run" => match rest.next().map(|n| n.parse()) {
+                Some(Ok(n)) => fuel = Some(n),
+                _ => return Err(CliError::Usage(format!("`{}` needs a number", arg))),
+            },
+            "--trace" if command == "run" => match rest.next() {
+                Some(path) => trace = Some(PathBuf::from(path)),
+                None => return Err(CliError::Usage(format!("`{}` needs a file name", arg))),
+            },
+            "--trace-format" if command == "run" => match rest.next().map(String::as_str) {
+                Some("text") => trace_format = Some(TraceFormat::Text),
+                Some("json") => trace_format = Some(TraceFormat::JsonLines),
+                _ => return Err(CliError::Usage(format!("`{}` needs `text` or `json`", arg))),
+            },
+            flag if flag.starts_with('-') => {
+                return Err(CliError::Usage(format!("unknown option `{}`", flag)));
+            }
+            path => inputs.push(PathBuf::from(path)),
+        }
+    }
+
+    let input = || match inputs.as_slice() {
+        [input] => Ok(input.clone()),
+        [] => Err(CliError::Usage(format!(
+            "`{}` needs an input file",
+            command
+        ))),
+        _ => Err(CliError::Usage(format!(
+            "`{}` takes a single input file",
+            command
+        ))),
+    };
+    if trace_format.is_some() && trace.is_none() {
+        return Err(CliError::Usage(
+            "`--trace-format` needs `--trace`".to_string(),
+        ));
+    }
+    match command {
+        "repl" if inputs.is_empty() => Ok(Command::Repl),
+        "asm" => {
+            let input = input()?;
+            let output = output.unwrap_or_else(|| input.with_extension(BYTECODE_EXTENSION));
+            Ok(Command::Asm { input, output })
+        }
+        "run" => Ok(Command::Run {
+            input: input()?,
+            fuel,
+            trace,
+            trace_format: trace_format.unwrap_or(TraceFormat::Text),
+        }),
+        "disasm" => Ok(Command::Disasm {
+            input: input()?,
+            show_bytes,
+        }),
+        _ => Err(CliError::Usage(format!(
+            "unknown command `{}`",
+            args.join(" ")
+        ))),
+    }
+}
+
+/// Runs `command` and returns the exit code the process should end with.
+pub fn execute(command: Command) -> Result<i32, CliError> {
+    match command {
+        Command::Repl => {
+            let mut repl = Repl::new();
+            repl.run()
+                .map_err(|e| CliError::Io(PathBuf::from("<stdin>"), e))?;
+            Ok(0)
+        }
+        Command::Asm { input, output } => {
+            let file = assemble_file(&input)?;
+            fs::write(&output, file.to_bytes()).map_err(|e| CliError::Io(output, e))?;
+            Ok(0)
+        }
+        Command::Run {
+            input,
+            fuel,
+            trace,
+            trace_format,
+        } => {
+            let file = load_file(&input)?;
+            let diagnostics = verify(&file.code);
+            if !diagnostics.is_empty() {
+                return Err(CliError::Verifier(diagnostics));
+            }
+            let mut vm = VM::new();
+            vm.register_default_host_fns();
+            vm.load_file(file);
+            if let Some(path) = &trace {
+                let out = fs::File::create(path).map_err(|e| CliError::Io(path.clone(), e))?;
+                vm.set_tracer(TraceWriter::new(io::BufWriter::new(out), trace_format));
+            }
+            let result = match fuel {
+                Some(fuel) => vm.run_with_fuel(fuel),
+                None => vm.run(),
+            };
+            // The trace is most useful when the program faults, so it is written out first
+            if let (Some(path), Some(mut tracer)) = (trace, vm.remove_tracer()) {
+                tracer.flush().map_err(|e| CliError::Io(path, e))?;
+            }
+            let reason = result?;
+            if reason == ExitReason::OutOfFuel {
+                return Err(CliError::OutOfFuel(vm.fuel_used()));
+            }
+            Ok(exit_code(&reason))
+        }
+        Command::Disasm { input, show_bytes } => {
+            let file = load_file(&input)?;
+            print!(
+                "{}",
+                listing(&file.code, &file.data, file.symbols.as_ref(), show_bytes)
+            );
+            Ok(0)
+        }
+    }
+}
+
+/// Process exit code for a program that stopped without faulting.
+pub fn exit_code(reason: &ExitReason) -> i32 {
+    match reason {
+        ExitReason::Halted | ExitReason::EndOfProgram | ExitReason::Stepped => 0,
+        ExitReason::Exited(code) => *code,
+        ExitReason::OutOfFuel => 1,
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, CliError> {
+    fs::read(path).map_err(|e| CliError::Io(path.to_path_buf(), e))
+}
+
+fn assemble_file(path: &Path) -> Result<BytecodeFile, CliError> {
+    let source = fs::read_to_string(path).map_err(|e| CliError::Io(path.to_path_buf(), e))?;
+    let mut assembler = Assembler::new();
+    let program = assembler.assemble(&source)?;
+    Ok(BytecodeFile::from_program(program, assembler.symbols()))
+}
+
+/// Reads a bytecode file, or assembles the file first if it does not start with the magic number.
+pub(crate) fn load_file(path: &Path) -> Result<BytecodeFile, CliError> {
+    let bytes = read(path)?;
+    if BytecodeFile::is_bytecode(&bytes) {
+        Ok(BytecodeFile::from_bytes(&bytes)?)
+    } else {
+        assemble_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("virian-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(&args("")).unwrap(), Command::Repl);
+        assert_eq!(parse_args(&args("repl")).unwrap(), Command::Repl);
+        assert_eq!(
+            parse_args(&args("asm in.s -o out.vbc")).unwrap(),
+            Command::Asm {
+                input: PathBuf::from("in.s"),
+                output: PathBuf::from("out.vbc"),
+            }
+        );
+        assert_eq!(
+            parse_args(&args("asm prog.s")).unwrap(),
+            Command::Asm {
+                input: PathBuf::from("prog.s"),
+                output: PathBuf::from("prog.vbc"),
+            }
+        );
+        assert_eq!(
+            parse_args(&args("disasm out.vbc --bytes")).unwrap(),
+            Command::Disasm {
+                input: PathBuf::from("out.vbc"),
+                show_bytes: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        for line in &[
+            "run",
+            "run a b",
+            "asm in.s -o",
+            "run x --bytes",
+            "run x --fuel",
+            "run x --fuel lots",
+            "run x --trace",
+            "run x --trace t.log --trace-format xml",
+            "run x --trace-format json",
+            "fly",
+        ] {
+            let e = parse_args(&args(line)).unwrap_err();
+            assert_eq!(e.exit_code(), 2, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_asm_then_run() {
+        let source = temp_path("asm.s");
+        let output = temp_path("asm.vbc");
+        fs::write(&source, "main: load $0 #1\nhlt\n").unwrap();
+        let command = Command::Asm {
+            input: source.clone(),
+            output: output.clone(),
+        };
+        assert_eq!(execute(command).unwrap(), 0);
+        assert!(BytecodeFile::is_bytecode(&fs::read(&output).unwrap()));
+
+        for input in &[source.clone(), output.clone()] {
+            assert_eq!(
+                execute(Command::Run {
+                    input: input.clone(),
+                    fuel: None,
+                    trace: None,
+                    trace_format: TraceFormat::Text,
+                })
+                .unwrap(),
+                0
+            );
+        }
+        fs::remove_file(source).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_asm_reports_every_error() {
+        let source = temp_path("errors.s");
+        fs::write(&source, "load $0 #1\nfly\nhlt\nhlt junk\n").unwrap();
+        let e = execute(Command::Run {
+            input: source.clone(),
+            fuel: None,
+            trace: None,
+            trace_format: TraceFormat::Text,
+        })
+        .unwrap_err();
+        let message = e.to_string();
+        assert!(message.contains(" --> 2:1"), "{}", message);
+        assert!(message.contains(" --> 4:5"), "{}", message);
+        assert!(message.ends_with("aborting due to 2 previous errors"));
+        fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    fn test_run_exit_code() {
+        let source = temp_path("exit.s");
+        fs::write(&source, "load $0 #3\nsyscall #3\nhlt\n").unwrap();
+        let code = execute(Command::Run {
+            input: source.clone(),
+            fuel: None,
+            trace: None,
+            trace_format: TraceFormat::Text,
+        })
+        .unwrap();
+        assert_eq!(code, 3);
+        fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    fn test_run_out_of_fuel() {
+        assert_eq!(
+            parse_args(&args("run loop.s --fuel 50")).unwrap(),
+            Command::Run {
+                input: PathBuf::from("loop.s"),
+                fuel: Some(50),
+                trace: None,
+                trace_format: TraceFormat::Text,
+            }
+        );
+
+        let source = temp_path("loop.s");
+        fs::write(&source, "load $0 #4\nloop: jmp $0\n").unwrap();
+        let e = execute(Command::Run {
+            input: source.clone(),
+            fuel: Some(50),
+            trace: None,
+            trace_format: TraceFormat::Text,
+        })
+        .unwrap_err();
+        assert_eq!(e.to_string(), "program ran out of fuel after 50 units");
+        assert_eq!(e.exit_code(), 1);
+        fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    fn test_run_writes_trace() {
+        assert_eq!(
+            parse_args(&args("run prog.s --trace t.jsonl --trace-format json")).unwrap(),
+            Command::Run {
+                input: PathBuf::from("prog.s"),
+                fuel: None,
+                trace: Some(PathBuf::from("t.jsonl")),
+                trace_format: TraceFormat::JsonLines,
+            }
+        );
+
+        let source = temp_path("trace.s");
+        let trace = temp_path("trace.log");
+        fs::write(&source, "load $0 #2\nload $1 #0\ndiv $0 $1 $2\n").unwrap();
+        let e = execute(Command::Run {
+            input: source.clone(),
+            fuel: None,
+            trace: Some(trace.clone()),
+            trace_format: TraceFormat::Text,
+        })
+        .unwrap_err();
+        assert!(e.to_string().starts_with("VM fault: division by zero"));
+        assert_eq!(
+            fs::read_to_string(&trace).unwrap(),
+            "0000: load $0 #2 ; $0: 0 -> 2\n0004: load $1 #0\n\
+             0008: div $0 $1 $2 ; fault: division by zero\n"
+        );
+        fs::remove_file(source).unwrap();
+        fs::remove_file(trace).unwrap();
+    }
+
+    #[test]
+    fn test_run_reports_faults() {
+        let source = temp_path("fault.s");
+        fs::write(&source, "load $1 #0\ndiv $0 $1 $2\n").unwrap();
+        let e = execute(Command::Run {
+            input: source.clone(),
+            fuel: None,
+            trace: None,
+            trace_format: TraceFormat::Text,
+        })
+        .unwrap_err();
+        assert_eq!(e.exit_code(), 1);
+        assert!(e.to_string().starts_with("VM fault: division by zero"));
+        fs::remove_file(source).unwrap();
+    }
+}
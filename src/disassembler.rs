@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::assembler::instruction_parser::AssemblerInstruction;
+use crate::assembler::{Section, SymbolTable, Token};
+use crate::instructions::{Opcode, OperandKind};
+
+/// A decoded instruction together with where it was found and the bytes it was decoded from.
+#[derive(Debug, PartialEq)]
+pub struct DisassembledInstruction {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    pub instruction: AssemblerInstruction,
+}
+
+impl DisassembledInstruction {
+    /// True for a byte that `disassemble` returns as `igl` because it does not start a valid
+    /// instruction.
+    pub fn is_illegal(&self) -> bool {
+        let opcode = Opcode::from(self.bytes[0]);
+        opcode == Opcode::IGL || self.bytes.len() < opcode.width()
+    }
+}
+
+/// Decodes `program` into instructions. Bytes that are not a valid opcode, and the bytes of an
+/// instruction that is cut off by the end of the program, names a register that does not exist,
+/// loads a NaN or infinite float or has non-zero padding, are decoded one at a time as `igl`.
+pub fn disassemble(program: &[u8]) -> Vec<DisassembledInstruction> {
+    let mut results = vec![];
+    let mut offset = 0;
+    while offset < program.len() {
+        let opcode = Opcode::from(program[offset]);
+        let width = opcode.width();
+        let values = program
+            .get(offset..offset + width)
+            .filter(|_| opcode != Opcode::IGL)
+            .and_then(|bytes| decode(opcode.clone(), bytes));
+        let values = match values {
+            Some(values) => values,
+            None => {
+                results.push(DisassembledInstruction {
+                    offset,
+                    bytes: vec![program[offset]],
+                    instruction: AssemblerInstruction::new(None, Opcode::IGL, vec![]),
+                });
+                offset += 1;
+                continue;
+            }
+        };
+
+        let bytes = &program[offset..offset + width];
+        results.push(DisassembledInstruction {
+            offset,
+            bytes: bytes.to_vec(),
+            instruction: instruction(opcode, &values),
+        });
+        offset += width;
+    }
+    results
+}
+
+/// Operand values of the instruction `bytes` holds, or `None` when an operand is out of range, a
+/// float is NaN or infinite or a padding byte is not zero, none of which the assembler writes.
+fn decode(opcode: Opcode, bytes: &[u8]) -> Option<[i64; 3]> {
+    let encoding = opcode.encoding();
+    let values = encoding.decode(&bytes[1..]);
+    let in_range = encoding.operands.iter().zip(&values).all(|(kind, value)| {
+        let (min, max) = kind.range();
+        match kind {
+            OperandKind::Float => f64::from_bits(*value as u64).is_finite(),
+            _ => (min..=max).contains(value),
+        }
+    });
+    let used = 1 + encoding
+        .operands
+        .iter()
+        .map(|kind| kind.width())
+        .sum::<usize>();
+    if in_range && bytes[used..].iter().all(|&byte| byte == 0) {
+        Some(values)
+    } else {
+        None
+    }
+}
+
+/// Builds the instruction `opcode` with operand values as `Encoding::decode` returns them.
+pub fn instruction(opcode: Opcode, values: &[i64]) -> AssemblerInstruction {
+    let operands = opcode
+        .encoding()
+        .operands
+        .iter()
+        .zip(values)
+        .map(|(kind, &value)| match kind {
+            OperandKind::Register => Token::register(value as u8),
+            OperandKind::Integer | OperandKind::Word => Token::operand(value as i32),
+            OperandKind::FloatRegister => Token::float_register(value as u8),
+            OperandKind::Float => Token::float_operand(f64::from_bits(value as u64)),
+        })
+        .collect();
+    AssemblerInstruction::new(None, opcode, operands)
+}
+
+/// Renders a program as assembly text, one instruction or directive per line, that assembles
+/// back to the same bytes. Bytes that do not decode are written as `.byte`. Read-only data follows
+/// a `.data` directive as `.asciiz` for printable NUL-terminated strings, `.word` and `.byte`,
+/// with `.code` put in front of the instructions. Labels from `symbols` are printed in front of
+/// what they mark. With `show_bytes` every line is prefixed with its offset in its section and its
+/// raw bytes, which makes the output unsuitable for the assembler.
+pub fn listing(
+    code: &[u8],
+    data: &[u8],
+    symbols: Option<&SymbolTable>,
+    show_bytes: bool,
+) -> String {
+    let code_labels = labels(symbols, Section::Code);
+    let data_labels = labels(symbols, Section::Data);
+
+    let mut output = String::new();
+    if !data.is_empty() {
+        writeln!(output, ".code").unwrap();
+    }
+    for decoded in disassemble(code) {
+        let text = if decoded.is_illegal() {
+            format!(".byte {}", decoded.bytes[0])
+        } else {
+            decoded.instruction.to_string()
+        };
+        let label = code_labels.get(&decoded.offset);
+        write_line(
+            &mut output,
+            decoded.offset,
+            &decoded.bytes,
+            label,
+            &text,
+            show_bytes,
+        );
+    }
+    if let Some(label) = code_labels.get(&code.len()) {
+        writeln!(output, "{}:", label).unwrap();
+    }
+
+    if !data.is_empty() {
+        writeln!(output, ".data").unwrap();
+        let mut offset = 0;
+        while offset < data.len() {
+            let end = data_labels
+                .keys()
+                .copied()
+                .filter(|&label| label > offset)
+                .min()
+                .unwrap_or(data.len())
+                .min(data.len());
+            let (len, text) = data_directive(&data[offset..end]);
+            let label = data_labels.get(&offset);
+            let bytes = &data[offset..offset + len];
+            write_line(&mut output, offset, bytes, label, &text, show_bytes);
+            offset += len;
+        }
+        if let Some(label) = data_labels.get(&data.len()) {
+            writeln!(output, "{}:", label).unwrap();
+        }
+    }
+    output
+}
+
+/// Offsets of the labels `symbols` declares in `section`. The first name wins where several
+/// labels share an offset.
+fn labels(symbols: Option<&SymbolTable>, section: Section) -> HashMap<usize, &str> {
+    let mut labels = HashMap::new();
+    if let Some(symbols) = symbols {
+        for (name, symbol) in symbols.iter() {
+            if symbol.section == section {
+                labels.entry(symbol.offset as usize).or_insert(name);
+            }
+        }
+    }
+    labels
+}
+
+fn write_line(
+    output: &mut String,
+    offset: usize,
+    bytes: &[u8],
+    label: Option<&&str>,
+    text: &str,
+    show_bytes: bool,
+) {
+    if show_bytes {
+        let raw: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(output, "{:04x}: {:<12} ", offset, raw.join(" ")).unwrap();
+    }
+    if let Some(label) = label {
+        write!(output, "{}: ", label).unwrap();
+    }
+    writeln!(output, "{}", text).unwrap();
+}
+
+/// Directive for the start of `data`, a run of data no label points into. Returns how many bytes
+/// it covers and its text.
+fn data_directive(data: &[u8]) -> (usize, String) {
+    if let Some(len) = data.iter().position(|&b| b == 0) {
+        let text = &data[..len];
+        let printable = |&b: &u8| b == b'\n' || b == b'\t' || (0x20..0x7f).contains(&b);
+        if len > 0 && text.iter().all(printable) {
+            let mut escaped = String::new();
+            for &b in text {
+                match b {
+                    b'\n' => escaped.push_str("\\n"),
+                    b'\t' => escaped.push_str("\\t"),
+                    b'"' => escaped.push_str("\\\""),
+                    b'\\' => escaped.push_str("\\\\"),
+                    b => escaped.push(b as char),
+                }
+            }
+            return (len + 1, format!(".asciiz \"{}\"", escaped));
+        }
+    }
+    if data.len() >= 4 {
+        let word = i32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        return (4, format!(".word {}", word));
+    }
+    (1, format!(".byte {}", data[0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_disassemble() {
+        let decoded = disassemble(&[0, 1, 1, 244, 9, 0, 1, 0, 6]);
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[0].instruction.to_string(), "load $1 #500");
+        assert_eq!(decoded[1].offset, 4);
+        assert_eq!(decoded[1].bytes, vec![9, 0, 1, 0]);
+        assert_eq!(decoded[1].instruction.to_string(), "eq $0 $1");
+        assert_eq!(decoded[2].instruction.to_string(), "hlt");
+    }
+
+    #[test]
+    fn test_disassemble_illegal_and_truncated() {
+        let decoded = disassemble(&[200, 0, 1]);
+        let text: Vec<String> = decoded.iter().map(|d| d.instruction.to_string()).collect();
+        assert_eq!(text, vec!["igl", "igl", "igl"]);
+    }
+
+    #[test]
+    fn test_listing_round_trips_bad_registers_and_padding() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[1, 0, 40, 2], ".byte 1\n.byte 0\nrem $2\n"),
+            (&[9, 0, 1, 7], ".byte 9\n.byte 0\n.byte 1\n.byte 7\n"),
+        ];
+        for &(code, expected) in cases {
+            let text = listing(code, &[], None, false);
+            assert_eq!(text, expected);
+            assert_eq!(Assembler::new().assemble(&text).unwrap().code, code);
+        }
+    }
+
+    #[test]
+    fn test_listing_round_trips() {
+        let source = "main: load $0 #3\nload $1 @end\nloop: eq $0 $2\njmpe $1\npush $0\npop $3\n\
+                      add $0 $4 $0\nloadi $6 #-3\nsub $0 $1 $2\nshr $2 $1 $3\nmov $3 $4\nnot $4 $5\nrem $5\nloadf $f0 #-2.5\nloadf $f1 #0.1\nloadf $f2 #6.02e23\nfdiv $f0 $f1 $f2\nflt $f2 $f0\nitof $3 $f4\nftoi $f4 $3\nloadw $5 $2\nstoreb $2 $5\ncall @loop\nend: hlt\n";
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(source).unwrap();
+
+        let text = listing(&program.code, &[], Some(assembler.symbols()), false);
+        assert!(text.starts_with("main: load $0 #3\n"));
+        let reassembled = Assembler::new().assemble(&text).unwrap();
+        assert_eq!(reassembled.code, program.code);
+
+        let text = listing(&program.code, &[], None, false);
+        assert_eq!(Assembler::new().assemble(&text).unwrap().code, program.code);
+    }
+
+    #[test]
+    fn test_listing_round_trips_data_and_illegal_bytes() {
+        let source =
+            "main: load $0 @msg\nloadcb $1 $0\nhlt\n.data\nmsg: .asciiz \"Hi \\\"you\\\"\\n\"\n\
+                      count: .word -2\nraw: .byte 0\n.byte 255\nend:\n";
+        let mut assembler = Assembler::new();
+        let mut program = assembler.assemble(source).unwrap();
+        program.code.extend_from_slice(&[200, 0, 1]);
+
+        let text = listing(
+            &program.code,
+            &program.data,
+            Some(assembler.symbols()),
+            false,
+        );
+        assert!(text.starts_with(".code\nmain: load $0 #0\n"));
+        assert!(text.contains("\n.byte 200\n.byte 0\n.byte 1\n.data\nmsg: .asciiz"));
+        assert!(text.contains("count: .word -2\nraw: .byte 0\n.byte 255\nend:\n"));
+        let mut reassembler = Assembler::new();
+        assert_eq!(reassembler.assemble(&text).unwrap(), program);
+        assert_eq!(reassembler.symbols(), assembler.symbols());
+
+        let text = listing(&program.code, &program.data, None, false);
+        assert_eq!(Assembler::new().assemble(&text).unwrap(), program);
+    }
+
+    #[test]
+    fn test_listing_round_trips_non_finite_floats() {
+        for value in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let mut code = vec![Opcode::LOADF as u8, 1];
+            code.extend_from_slice(&value.to_bits().to_be_bytes());
+            let text = listing(&code, &[], None, false);
+            assert!(text.starts_with(".byte "));
+            assert!(!text.contains("loadf"));
+            assert_eq!(Assembler::new().assemble(&text).unwrap().code, code);
+        }
+    }
+
+    #[test]
+    fn test_listing_with_bytes() {
+        let text = listing(&[0, 0, 1, 244, 6], &[], None, true);
+        assert_eq!(
+            text,
+            "0000: 00 00 01 f4  load $0 #500\n0004: 06           hlt\n"
+        );
+    }
+}
@@ -1,50 +1,385 @@
+use std::convert::TryInto;
+
 use nom::types::CompleteStr;
 
+use crate::vm::{FLOAT_REGISTER_COUNT, REGISTER_COUNT};
+
+/// Instruction set of the VM. The discriminant is the byte the opcode is encoded as.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Opcode {
-    LOAD,
-    ADD,
-    SUB,
-    MUL,
-    DIV,
-    JMP,
-    JMPF,
-    JMPB,
-    EQ,
-    NEQ,
-    LT,
-    GT,
-    LTQ,
-    GTQ,
-    JEQ,
-    JNEQ,
-    ALOC,
-    HLT,
-    IGL,
+    LOAD = 0,
+    ADD = 1,
+    SUB = 2,
+    MUL = 3,
+    DIV = 4,
+    JMP = 5,
+    HLT = 6,
+    JMPF = 7,
+    JMPB = 8,
+    EQ = 9,
+    NEQ = 10,
+    LT = 11,
+    LTQ = 12,
+    GT = 13,
+    GTQ = 14,
+    JEQ = 15,
+    JNEQ = 16,
+    ALOC = 17,
+    PUSH = 18,
+    POP = 19,
+    CALL = 20,
+    RET = 21,
+    LOADB = 22,
+    STOREB = 23,
+    LOADW = 24,
+    STOREW = 25,
+    FREE = 26,
+    SHRINK = 27,
+    LOADCB = 28,
+    LOADCW = 29,
+    LOADI = 30,
+    AND = 31,
+    OR = 32,
+    XOR = 33,
+    NOT = 34,
+    SHL = 35,
+    SHR = 36,
+    SAR = 37,
+    MOD = 38,
+    MOV = 39,
+    REM = 40,
+    JZ = 41,
+    JNZ = 42,
+    JC = 43,
+    JNC = 44,
+    JO = 45,
+    JNO = 46,
+    JN = 47,
+    JNN = 48,
+    LOADF = 49,
+    FADD = 50,
+    FSUB = 51,
+    FMUL = 52,
+    FDIV = 53,
+    FEQ = 54,
+    FNEQ = 55,
+    FLT = 56,
+    FLTQ = 57,
+    FGT = 58,
+    FGTQ = 59,
+    ITOF = 60,
+    FTOI = 61,
+    SYSCALL = 62,
+    IGL = 255,
+}
+
+/// Kind of a single operand in the encoded form of an instruction.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OperandKind {
+    /// One byte naming a register.
+    Register,
+    /// A 16-bit unsigned immediate, written as `#n` or `@label`.
+    Integer,
+    /// A 32-bit signed immediate, written like `Integer`.
+    Word,
+    /// One byte naming a float register, written as `$f0`.
+    FloatRegister,
+    /// A 64-bit float immediate, written as `#3.14` or as an integer.
+    Float,
+}
+
+impl OperandKind {
+    /// Number of bytes the operand is encoded in.
+    pub fn width(self) -> usize {
+        match self {
+            OperandKind::Register | OperandKind::FloatRegister => 1,
+            OperandKind::Integer => 2,
+            OperandKind::Word => 4,
+            OperandKind::Float => 8,
+        }
+    }
+
+    /// True for operands that name a register rather than hold a value.
+    pub fn is_register(self) -> bool {
+        self == OperandKind::Register || self == OperandKind::FloatRegister
+    }
+
+    /// Smallest and largest value the operand can hold. Floats are not range checked.
+    pub fn range(self) -> (i64, i64) {
+        match self {
+            OperandKind::Register => (0, REGISTER_COUNT as i64 - 1),
+            OperandKind::Integer => (0, i64::from(u16::MAX)),
+            OperandKind::Word => (i64::from(i32::MIN), i64::from(i32::MAX)),
+            OperandKind::FloatRegister => (0, FLOAT_REGISTER_COUNT as i64 - 1),
+            OperandKind::Float => (i64::MIN, i64::MAX),
+        }
+    }
+
+    /// How the operand is described in assembler error messages.
+    pub fn describe(self) -> &'static str {
+        match self {
+            OperandKind::Register => "register",
+            OperandKind::Integer | OperandKind::Word => "integer operand",
+            OperandKind::FloatRegister => "float register",
+            OperandKind::Float => "float operand",
+        }
+    }
+
+    /// Appends `value` big-endian in `width()` bytes, truncating it if it does not fit. Floats are
+    /// passed as the bits of the `f64`.
+    pub fn encode(self, value: i64, results: &mut Vec<u8>) {
+        match self {
+            OperandKind::Register | OperandKind::FloatRegister => results.push(value as u8),
+            OperandKind::Integer => results.extend_from_slice(&(value as u16).to_be_bytes()),
+            OperandKind::Word => results.extend_from_slice(&(value as i32).to_be_bytes()),
+            OperandKind::Float => results.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+
+    /// Reads the operand from the start of `bytes`, which must hold at least `width()` bytes.
+    /// Floats decode to the bits of the `f64`.
+    pub fn decode(self, bytes: &[u8]) -> i64 {
+        match self {
+            OperandKind::Register | OperandKind::FloatRegister => i64::from(bytes[0]),
+            OperandKind::Integer => i64::from(u16::from_be_bytes([bytes[0], bytes[1]])),
+            OperandKind::Word => i64::from(i32::from_be_bytes(bytes[..4].try_into().unwrap())),
+            OperandKind::Float => i64::from_be_bytes(bytes[..8].try_into().unwrap()),
+        }
+    }
+}
+
+/// How an opcode is written and encoded: its operands in the order they appear in both the
+/// source and the bytecode, and the width of the whole instruction. Bytes after the operands are
+/// padding.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Encoding {
+    pub operands: &'static [OperandKind],
+    pub width: usize,
+}
+
+impl Encoding {
+    /// Decodes the operands from `bytes`, the instruction without its opcode byte. Registers decode
+    /// to their number and immediates to their value. Slots without an operand are 0.
+    pub fn decode(&self, bytes: &[u8]) -> [i64; 3] {
+        let mut operands = [0; 3];
+        let mut position = 0;
+        for (slot, kind) in operands.iter_mut().zip(self.operands) {
+            *slot = kind.decode(&bytes[position..]);
+            position += kind.width();
+        }
+        operands
+    }
+}
+
+impl Opcode {
+    /// Operand layout and width of every opcode. The assembler, VM, verifier and disassembler all
+    /// read instructions through this table.
+    pub fn encoding(&self) -> Encoding {
+        use self::OperandKind::*;
+        let (operands, width): (&'static [OperandKind], usize) = match self {
+            Opcode::LOAD => (&[Register, Integer], 4),
+            Opcode::LOADI => (&[Register, Word], 6),
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::DIV
+            | Opcode::MOD
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::SHL
+            | Opcode::SHR
+            | Opcode::SAR => (&[Register, Register, Register], 4),
+            Opcode::EQ | Opcode::NEQ | Opcode::LT | Opcode::GT | Opcode::LTQ | Opcode::GTQ => {
+                (&[Register, Register], 4)
+            }
+            Opcode::JMP
+            | Opcode::JMPF
+            | Opcode::JMPB
+            | Opcode::JEQ
+            | Opcode::JNEQ
+            | Opcode::ALOC
+            | Opcode::PUSH
+            | Opcode::POP
+            | Opcode::SHRINK
+            | Opcode::REM
+            | Opcode::JZ
+            | Opcode::JNZ
+            | Opcode::JC
+            | Opcode::JNC
+            | Opcode::JO
+            | Opcode::JNO
+            | Opcode::JN
+            | Opcode::JNN => (&[Register], 2),
+            Opcode::LOADB
+            | Opcode::STOREB
+            | Opcode::LOADW
+            | Opcode::STOREW
+            | Opcode::LOADCB
+            | Opcode::LOADCW
+            | Opcode::NOT
+            | Opcode::MOV => (&[Register, Register], 3),
+            Opcode::LOADF => (&[FloatRegister, Float], 10),
+            Opcode::FADD | Opcode::FSUB | Opcode::FMUL | Opcode::FDIV => {
+                (&[FloatRegister, FloatRegister, FloatRegister], 4)
+            }
+            Opcode::FEQ
+            | Opcode::FNEQ
+            | Opcode::FLT
+            | Opcode::FLTQ
+            | Opcode::FGT
+            | Opcode::FGTQ => (&[FloatRegister, FloatRegister], 4),
+            Opcode::ITOF => (&[Register, FloatRegister], 3),
+            Opcode::FTOI => (&[FloatRegister, Register], 3),
+            Opcode::CALL | Opcode::SYSCALL => (&[Integer], 3),
+            Opcode::HLT | Opcode::RET | Opcode::FREE | Opcode::IGL => (&[], 1),
+        };
+        Encoding { operands, width }
+    }
+
+    /// Number of bytes an encoded instruction occupies, opcode byte included.
+    pub fn width(&self) -> usize {
+        self.encoding().width
+    }
+
+    /// Assembler mnemonic, the inverse of `From<CompleteStr>`.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::LOAD => "load",
+            Opcode::ADD => "add",
+            Opcode::SUB => "sub",
+            Opcode::MUL => "mul",
+            Opcode::DIV => "div",
+            Opcode::JMP => "jmp",
+            Opcode::HLT => "hlt",
+            Opcode::JMPF => "jmpf",
+            Opcode::JMPB => "jmpb",
+            Opcode::EQ => "eq",
+            Opcode::NEQ => "neq",
+            Opcode::LT => "lt",
+            Opcode::LTQ => "lte",
+            Opcode::GT => "gt",
+            Opcode::GTQ => "gte",
+            Opcode::JEQ => "jmpe",
+            Opcode::JNEQ => "jmpne",
+            Opcode::ALOC => "aloc",
+            Opcode::PUSH => "push",
+            Opcode::POP => "pop",
+            Opcode::CALL => "call",
+            Opcode::RET => "ret",
+            Opcode::LOADB => "loadb",
+            Opcode::STOREB => "storeb",
+            Opcode::LOADW => "loadw",
+            Opcode::STOREW => "storew",
+            Opcode::FREE => "free",
+            Opcode::SHRINK => "shrink",
+            Opcode::LOADCB => "loadcb",
+            Opcode::LOADCW => "loadcw",
+            Opcode::LOADI => "loadi",
+            Opcode::AND => "and",
+            Opcode::OR => "or",
+            Opcode::XOR => "xor",
+            Opcode::NOT => "not",
+            Opcode::SHL => "shl",
+            Opcode::SHR => "shr",
+            Opcode::SAR => "sar",
+            Opcode::MOD => "mod",
+            Opcode::MOV => "mov",
+            Opcode::REM => "rem",
+            Opcode::JZ => "jmpz",
+            Opcode::JNZ => "jmpnz",
+            Opcode::JC => "jmpc",
+            Opcode::JNC => "jmpnc",
+            Opcode::JO => "jmpo",
+            Opcode::JNO => "jmpno",
+            Opcode::JN => "jmpn",
+            Opcode::JNN => "jmpnn",
+            Opcode::LOADF => "loadf",
+            Opcode::FADD => "fadd",
+            Opcode::FSUB => "fsub",
+            Opcode::FMUL => "fmul",
+            Opcode::FDIV => "fdiv",
+            Opcode::FEQ => "feq",
+            Opcode::FNEQ => "fneq",
+            Opcode::FLT => "flt",
+            Opcode::FLTQ => "flte",
+            Opcode::FGT => "fgt",
+            Opcode::FGTQ => "fgte",
+            Opcode::ITOF => "itof",
+            Opcode::FTOI => "ftoi",
+            Opcode::SYSCALL => "syscall",
+            Opcode::IGL => "igl",
+        }
+    }
 }
 
 impl From<u8> for Opcode {
     fn from(v: u8) -> Self {
         match v {
-            0 => return Opcode::LOAD,
-            1 => return Opcode::ADD,
-            2 => return Opcode::SUB,
-            3 => return Opcode::MUL,
-            4 => return Opcode::DIV,
-            5 => return Opcode::JMP,
-            6 => return Opcode::HLT,
-            7 => return Opcode::JMPF,
-            8 => return Opcode::JMPB,
-            9 => return Opcode::EQ,
-            10 => return Opcode::NEQ,
-            11 => return Opcode::LT,
-            12 => return Opcode::LTQ,
-            13 => return Opcode::GT,
-            14 => return Opcode::GTQ,
-            15 => return Opcode::JEQ,
-            16 => return Opcode::JNEQ,
-            17 => return Opcode::ALOC,
-            _ => return Opcode::IGL,
+            0 => Opcode::LOAD,
+            1 => Opcode::ADD,
+            2 => Opcode::SUB,
+            3 => Opcode::MUL,
+            4 => Opcode::DIV,
+            5 => Opcode::JMP,
+            6 => Opcode::HLT,
+            7 => Opcode::JMPF,
+            8 => Opcode::JMPB,
+            9 => Opcode::EQ,
+            10 => Opcode::NEQ,
+            11 => Opcode::LT,
+            12 => Opcode::LTQ,
+            13 => Opcode::GT,
+            14 => Opcode::GTQ,
+            15 => Opcode::JEQ,
+            16 => Opcode::JNEQ,
+            17 => Opcode::ALOC,
+            18 => Opcode::PUSH,
+            19 => Opcode::POP,
+            20 => Opcode::CALL,
+            21 => Opcode::RET,
+            22 => Opcode::LOADB,
+            23 => Opcode::STOREB,
+            24 => Opcode::LOADW,
+            25 => Opcode::STOREW,
+            26 => Opcode::FREE,
+            27 => Opcode::SHRINK,
+            28 => Opcode::LOADCB,
+            29 => Opcode::LOADCW,
+            30 => Opcode::LOADI,
+            31 => Opcode::AND,
+            32 => Opcode::OR,
+            33 => Opcode::XOR,
+            34 => Opcode::NOT,
+            35 => Opcode::SHL,
+            36 => Opcode::SHR,
+            37 => Opcode::SAR,
+            38 => Opcode::MOD,
+            39 => Opcode::MOV,
+            40 => Opcode::REM,
+            41 => Opcode::JZ,
+            42 => Opcode::JNZ,
+            43 => Opcode::JC,
+            44 => Opcode::JNC,
+            45 => Opcode::JO,
+            46 => Opcode::JNO,
+            47 => Opcode::JN,
+            48 => Opcode::JNN,
+            49 => Opcode::LOADF,
+            50 => Opcode::FADD,
+            51 => Opcode::FSUB,
+            52 => Opcode::FMUL,
+            53 => Opcode::FDIV,
+            54 => Opcode::FEQ,
+            55 => Opcode::FNEQ,
+            56 => Opcode::FLT,
+            57 => Opcode::FLTQ,
+            58 => Opcode::FGT,
+            59 => Opcode::FGTQ,
+            60 => Opcode::ITOF,
+            61 => Opcode::FTOI,
+            62 => Opcode::SYSCALL,
+            _ => Opcode::IGL,
         }
     }
 }
@@ -70,7 +405,53 @@ impl<'a> From<CompleteStr<'a>> for Opcode {
             CompleteStr("lte") => Opcode::LTQ,
             CompleteStr("lt") => Opcode::LT,
             CompleteStr("jmpe") => Opcode::JEQ,
+            CompleteStr("jmpne") => Opcode::JNEQ,
             CompleteStr("aloc") => Opcode::ALOC,
+            CompleteStr("push") => Opcode::PUSH,
+            CompleteStr("pop") => Opcode::POP,
+            CompleteStr("call") => Opcode::CALL,
+            CompleteStr("ret") => Opcode::RET,
+            CompleteStr("loadb") => Opcode::LOADB,
+            CompleteStr("storeb") => Opcode::STOREB,
+            CompleteStr("loadw") => Opcode::LOADW,
+            CompleteStr("storew") => Opcode::STOREW,
+            CompleteStr("free") => Opcode::FREE,
+            CompleteStr("shrink") => Opcode::SHRINK,
+            CompleteStr("loadcb") => Opcode::LOADCB,
+            CompleteStr("loadcw") => Opcode::LOADCW,
+            CompleteStr("loadi") => Opcode::LOADI,
+            CompleteStr("and") => Opcode::AND,
+            CompleteStr("or") => Opcode::OR,
+            CompleteStr("xor") => Opcode::XOR,
+            CompleteStr("not") => Opcode::NOT,
+            CompleteStr("shl") => Opcode::SHL,
+            CompleteStr("shr") => Opcode::SHR,
+            CompleteStr("sar") => Opcode::SAR,
+            CompleteStr("mod") => Opcode::MOD,
+            CompleteStr("mov") => Opcode::MOV,
+            CompleteStr("rem") => Opcode::REM,
+            CompleteStr("jmpz") => Opcode::JZ,
+            CompleteStr("jmpnz") => Opcode::JNZ,
+            CompleteStr("jmpc") => Opcode::JC,
+            CompleteStr("jmpnc") => Opcode::JNC,
+            CompleteStr("jmpo") => Opcode::JO,
+            CompleteStr("jmpno") => Opcode::JNO,
+            CompleteStr("jmpn") => Opcode::JN,
+            CompleteStr("jmpnn") => Opcode::JNN,
+            CompleteStr("loadf") => Opcode::LOADF,
+            CompleteStr("fadd") => Opcode::FADD,
+            CompleteStr("fsub") => Opcode::FSUB,
+            CompleteStr("fmul") => Opcode::FMUL,
+            CompleteStr("fdiv") => Opcode::FDIV,
+            CompleteStr("feq") => Opcode::FEQ,
+            CompleteStr("fneq") => Opcode::FNEQ,
+            CompleteStr("flt") => Opcode::FLT,
+            CompleteStr("flte") => Opcode::FLTQ,
+            CompleteStr("fgt") => Opcode::FGT,
+            CompleteStr("fgte") => Opcode::FGTQ,
+            CompleteStr("itof") => Opcode::ITOF,
+            CompleteStr("ftoi") => Opcode::FTOI,
+            CompleteStr("syscall") => Opcode::SYSCALL,
             _ => Opcode::IGL,
         }
     }
@@ -97,6 +478,61 @@ mod tests {
         assert_eq!(opcode, Opcode::HLT);
     }
 
+    #[test]
+    fn test_opcode_width() {
+        assert_eq!(Opcode::LOAD.width(), 4);
+        assert_eq!(Opcode::JMP.width(), 2);
+        assert_eq!(Opcode::HLT.width(), 1);
+        assert_eq!(Opcode::LOADI.width(), 6);
+        assert_eq!(Opcode::LOADF.width(), 10);
+    }
+
+    #[test]
+    fn test_encoding_fits_width() {
+        for byte in 0..=62 {
+            let encoding = Opcode::from(byte).encoding();
+            let operands_len: usize = encoding.operands.iter().map(|kind| kind.width()).sum();
+            assert!(operands_len < encoding.width, "opcode {}", byte);
+            assert!(encoding.operands.len() <= 3, "opcode {}", byte);
+        }
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let encoding = Opcode::LOADI.encoding();
+        let mut bytes = vec![];
+        OperandKind::Register.encode(7, &mut bytes);
+        OperandKind::Word.encode(-70000, &mut bytes);
+        assert_eq!(encoding.decode(&bytes), [7, -70000, 0]);
+
+        let encoding = Opcode::LOAD.encoding();
+        assert_eq!(encoding.decode(&[1, 255, 255, 0]), [1, 65535, 0]);
+
+        let encoding = Opcode::LOADF.encoding();
+        let mut bytes = vec![];
+        OperandKind::FloatRegister.encode(3, &mut bytes);
+        OperandKind::Float.encode((-2.5f64).to_bits() as i64, &mut bytes);
+        let operands = encoding.decode(&bytes);
+        assert_eq!(operands[0], 3);
+        assert_eq!(f64::from_bits(operands[1] as u64), -2.5);
+    }
+
+    #[test]
+    fn test_opcode_byte_round_trip() {
+        for byte in 0..=62 {
+            assert_eq!(Opcode::from(byte) as u8, byte);
+        }
+        assert_eq!(Opcode::from(200), Opcode::IGL);
+    }
+
+    #[test]
+    fn test_mnemonic_round_trip() {
+        for byte in 0..=62 {
+            let opcode = Opcode::from(byte);
+            assert_eq!(Opcode::from(CompleteStr(opcode.mnemonic())), opcode);
+        }
+    }
+
     #[test]
     fn test_create_instruction() {
         let instruction = Instruction::new(Opcode::HLT);
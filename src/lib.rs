@@ -0,0 +1,27 @@
+//! Register based virtual machine with its assembler, bytecode format, verifier and
+//! disassembler.
+//!
+//! ```
+//! let code = virian::assemble("load $0 #20\nload $1 #22\nadd $0 $1 $2\nhlt").unwrap();
+//! let mut vm = virian::VM::with_program(code);
+//! vm.run().unwrap();
+//! assert_eq!(vm.registers()[2], 42);
+//! ```
+
+pub use crate::assembler::{Assembler, AssemblerError};
+pub use crate::vm::{ExitReason, VmError, VM};
+
+pub mod assembler;
+pub mod bytecode;
+pub mod disassembler;
+pub mod instructions;
+pub mod verifier;
+pub mod vm;
+
+/// Assembles a program without a `.data` section into bytecode for `VM::with_program`. Every
+/// error found is returned, rather than only the first one as a single `AssemblerError`, so a
+/// caller can report them all at once like the CLI does. Programs with data go through
+/// `Assembler` and `bytecode::BytecodeFile` instead.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
+    Assembler::new().assemble_code(source)
+}
@@ -1,12 +1,14 @@
-use crate::repl::REPL;
-use crate::vm::VM;
-
-pub mod assembler;
-pub mod instructions;
-pub mod repl;
-pub mod vm;
+mod cli;
+mod repl;
 
 fn main() {
-    let mut repl = REPL::new();
-    repl.run().unwrap();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = cli::parse_args(&args).and_then(cli::execute);
+    match result {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("virian: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
 }
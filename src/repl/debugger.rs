@@ -0,0 +1,453 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::ops::Range;
+
+use virian::assembler::{Section, SymbolTable};
+use virian::disassembler::disassemble;
+use virian::vm::REGISTER_COUNT;
+use virian::{ExitReason, VM};
+
+/// Number of instructions `disasm here` prints.
+const DISASM_HERE_LEN: usize = 6;
+
+/// Most instructions a single `step`, `next` or `continue` runs before handing control back, so a
+/// program that never stops cannot hang the REPL.
+const RESUME_LIMIT: usize = 1_000_000;
+
+/// REPL command understood by the debugger.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Break(Location),
+    Step,
+    Next,
+    Continue,
+    Watch(Watch),
+    Pc,
+    DisasmHere,
+    Backtrace,
+}
+
+/// Place in the program a breakpoint is set at.
+#[derive(Debug, PartialEq)]
+pub enum Location {
+    Offset(usize),
+    Label(String),
+}
+
+/// State the debugger stops on when it changes.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Watch {
+    Register(usize),
+    Heap(Range<usize>),
+}
+
+impl Watch {
+    /// Current value, `None` for heap bytes past the end of the heap.
+    fn value(&self, vm: &VM) -> Option<Vec<i64>> {
+        match self {
+            Watch::Register(index) => Some(vec![i64::from(vm.registers()[*index])]),
+            Watch::Heap(range) => vm
+                .heap()
+                .get(range.clone())
+                .map(|bytes| bytes.iter().map(|&b| i64::from(b)).collect()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Watch::Register(index) => format!("${}", index),
+            Watch::Heap(range) => format!("heap[{}..{}]", range.start, range.end),
+        }
+    }
+}
+
+/// Parses `line` as a debugger command. Returns `None` when the line is not one, so the REPL can
+/// treat it as assembly, and an error message when it is one but malformed.
+pub fn parse_command(line: &str) -> Option<Result<Command, String>> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let command = match words.as_slice() {
+        ["step"] => Ok(Command::Step),
+        ["next"] => Ok(Command::Next),
+        ["continue"] => Ok(Command::Continue),
+        ["pc"] => Ok(Command::Pc),
+        ["disasm", "here"] => Ok(Command::DisasmHere),
+        ["backtrace"] => Ok(Command::Backtrace),
+        ["break", location] => Ok(Command::Break(parse_location(location))),
+        ["watch", target] => parse_watch(target).map(Command::Watch),
+        ["break", ..] => Err("usage: break <offset|label>".to_string()),
+        ["watch", ..] => Err("usage: watch $<register> or watch heap[<start>..<end>]".to_string()),
+        _ => return None,
+    };
+    Some(command)
+}
+
+fn parse_offset(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_location(text: &str) -> Location {
+    match parse_offset(text) {
+        Some(offset) => Location::Offset(offset),
+        None => Location::Label(text.trim_start_matches('@').to_string()),
+    }
+}
+
+fn parse_watch(text: &str) -> Result<Watch, String> {
+    if let Some(register) = text.strip_prefix('$') {
+        return match register.parse() {
+            Ok(index) if index < REGISTER_COUNT => Ok(Watch::Register(index)),
+            _ => Err(format!("`{}` is not a register", text)),
+        };
+    }
+    let range = text
+        .strip_prefix("heap[")
+        .and_then(|rest| rest.strip_suffix(']'))
+        .and_then(|range| {
+            let mut bounds = range.splitn(2, "..");
+            Some(parse_offset(bounds.next()?)?..parse_offset(bounds.next()?)?)
+        });
+    match range {
+        Some(range) if range.start < range.end => Ok(Watch::Heap(range)),
+        _ => Err(format!("`{}` is not a register or heap range", text)),
+    }
+}
+
+/// How far `resume` runs the program.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Mode {
+    /// A single instruction.
+    Step,
+    /// A single instruction, running calls to completion.
+    Next,
+    /// Until something stops the program.
+    Continue,
+}
+
+/// Breakpoints, watchpoints and the symbols used to show and set them. Runs the VM one
+/// instruction at a time with `VM::run_once` and checks them in between.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    watches: Vec<Watch>,
+    symbols: Option<SymbolTable>,
+}
+
+impl Debugger {
+    /// Replaces the symbols of the program being debugged and drops all breakpoints and
+    /// watchpoints, which belonged to the previous one.
+    pub fn load_symbols(&mut self, symbols: Option<SymbolTable>) {
+        self.breakpoints.clear();
+        self.watches.clear();
+        self.symbols = symbols;
+    }
+
+    /// Runs `command` against `vm` and returns the text to show for it.
+    pub fn execute(&mut self, vm: &mut VM, command: Command) -> String {
+        match command {
+            Command::Break(location) => {
+                let offset = match location {
+                    Location::Offset(offset) => offset,
+                    Location::Label(name) => match self.code_label(&name) {
+                        Some(offset) => offset,
+                        None => return format!("unknown label `{}`", name),
+                    },
+                };
+                self.breakpoints.insert(offset);
+                format!("Breakpoint set at {}", self.describe(offset))
+            }
+            Command::Watch(watch) => {
+                let text = format!("Watching {}", watch.describe());
+                self.watches.push(watch);
+                text
+            }
+            Command::Step => self.resume(vm, Mode::Step),
+            Command::Next => self.resume(vm, Mode::Next),
+            Command::Continue => self.resume(vm, Mode::Continue),
+            Command::Pc => self.describe(vm.pc()),
+            Command::DisasmHere => self.disasm_here(vm),
+            Command::Backtrace => {
+                let mut text = format!("#0 {}", self.describe(vm.pc()));
+                for (depth, &address) in vm.call_stack().iter().rev().enumerate() {
+                    write!(text, "\n#{} {}", depth + 1, self.describe(address)).unwrap();
+                }
+                text
+            }
+        }
+    }
+
+    /// Runs the program as far as `mode` says, or until it reaches a breakpoint, changes a watched
+    /// value, stops, faults or has run `RESUME_LIMIT` instructions. The breakpoint at the starting
+    /// point is ignored so `continue` can leave it.
+    fn resume(&mut self, vm: &mut VM, mode: Mode) -> String {
+        let depth = vm.call_stack().len();
+        for executed in 0..RESUME_LIMIT {
+            if executed > 0 && self.breakpoints.contains(&vm.pc()) {
+                return format!(
+                    "Breakpoint at {}\n{}",
+                    self.describe(vm.pc()),
+                    self.current(vm)
+                );
+            }
+
+            let before: Vec<_> = self.watches.iter().map(|watch| watch.value(vm)).collect();
+            let reason = match vm.run_once() {
+                Ok(reason) => reason,
+                Err(e) => return format!("VM fault: {}", e),
+            };
+            for (watch, old) in self.watches.iter().zip(before) {
+                let new = watch.value(vm);
+                if new != old {
+                    return format!(
+                        "{} changed: {} -> {}\n{}",
+                        watch.describe(),
+                        show_value(&old),
+                        show_value(&new),
+                        self.current(vm)
+                    );
+                }
+            }
+            match reason {
+                ExitReason::Stepped => {}
+                ExitReason::Halted => return "HLT encountered".to_string(),
+                ExitReason::EndOfProgram => return "End of program".to_string(),
+                reason => return format!("Program stopped: {:?}", reason),
+            }
+            let done = match mode {
+                Mode::Step => true,
+                Mode::Next => vm.call_stack().len() <= depth,
+                Mode::Continue => false,
+            };
+            if done {
+                return self.current(vm);
+            }
+        }
+        format!(
+            "Stopped after {} instructions\n{}",
+            RESUME_LIMIT,
+            self.current(vm)
+        )
+    }
+
+    /// The instruction at the program counter.
+    fn current(&self, vm: &VM) -> String {
+        let program = vm.program();
+        match disassemble(&program[vm.pc().min(program.len())..]).first() {
+            Some(decoded) => format!("=> {}: {}", self.describe(vm.pc()), decoded.instruction),
+            None => format!("=> {}: end of program", self.describe(vm.pc())),
+        }
+    }
+
+    fn disasm_here(&self, vm: &VM) -> String {
+        let mut text = String::new();
+        for decoded in disassemble(vm.program())
+            .into_iter()
+            .skip_while(|decoded| decoded.offset < vm.pc())
+            .take(DISASM_HERE_LEN)
+        {
+            let marker = if decoded.offset == vm.pc() {
+                "=>"
+            } else {
+                "  "
+            };
+            writeln!(
+                text,
+                "{} {}: {}",
+                marker,
+                self.describe(decoded.offset),
+                decoded.instruction
+            )
+            .unwrap();
+        }
+        text.pop();
+        text
+    }
+
+    fn code_labels(&self) -> Vec<(&str, usize)> {
+        let mut labels: Vec<(&str, usize)> = self
+            .symbols
+            .iter()
+            .flat_map(|symbols| symbols.iter())
+            .filter(|(_, symbol)| symbol.section == Section::Code)
+            .map(|(name, symbol)| (name, symbol.offset as usize))
+            .collect();
+        labels.sort_by_key(|&(name, offset)| (offset, name));
+        labels
+    }
+
+    fn code_label(&self, name: &str) -> Option<usize> {
+        self.code_labels()
+            .into_iter()
+            .find(|&(label, _)| label == name)
+            .map(|(_, offset)| offset)
+    }
+
+    /// Offset in the hex form listings use, with the closest label before it if there is one.
+    fn describe(&self, offset: usize) -> String {
+        let label = self
+            .code_labels()
+            .into_iter()
+            .filter(|&(_, label_offset)| label_offset <= offset)
+            .max_by_key(|&(name, label_offset)| (label_offset, std::cmp::Reverse(name)));
+        match label {
+            Some((name, label_offset)) if label_offset == offset => {
+                format!("{:04x} <{}>", offset, name)
+            }
+            Some((name, label_offset)) => {
+                format!("{:04x} <{}+{}>", offset, name, offset - label_offset)
+            }
+            None => format!("{:04x}", offset),
+        }
+    }
+}
+
+fn show_value(value: &Option<Vec<i64>>) -> String {
+    match value {
+        Some(values) if values.len() == 1 => values[0].to_string(),
+        Some(values) => format!("{:?}", values),
+        None => "unallocated".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use virian::Assembler;
+
+    const PROGRAM: &str =
+        "main: load $0 #3\nload $1 @double\ncall @double\nhlt\ndouble: add $0 $0 $0\nret\n";
+
+    fn get_test_debugger() -> (Debugger, VM) {
+        let mut assembler = Assembler::new();
+        let code = assembler.assemble(PROGRAM).unwrap().code;
+        let mut debugger = Debugger::default();
+        debugger.load_symbols(Some(assembler.symbols().clone()));
+        (debugger, VM::with_program(code))
+    }
+
+    fn run(debugger: &mut Debugger, vm: &mut VM, line: &str) -> String {
+        let command = parse_command(line).unwrap().unwrap();
+        debugger.execute(vm, command)
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("load $0 #1"), None);
+        assert_eq!(
+            parse_command("break 0x10"),
+            Some(Ok(Command::Break(Location::Offset(16))))
+        );
+        assert_eq!(
+            parse_command("break @loop"),
+            Some(Ok(Command::Break(Location::Label("loop".to_string()))))
+        );
+        assert_eq!(
+            parse_command("watch heap[100..104]"),
+            Some(Ok(Command::Watch(Watch::Heap(100..104))))
+        );
+        assert_eq!(
+            parse_command("watch $3"),
+            Some(Ok(Command::Watch(Watch::Register(3))))
+        );
+        assert!(parse_command("watch $32").unwrap().is_err());
+        assert!(parse_command("watch heap[4..2]").unwrap().is_err());
+        assert!(parse_command("break").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_continue_stops_endless_loops() {
+        let mut debugger = Debugger::default();
+        let mut vm = VM::with_program(
+            Assembler::new()
+                .assemble("load $0 #4\njmp $0\n")
+                .unwrap()
+                .code,
+        );
+        assert_eq!(
+            run(&mut debugger, &mut vm, "continue"),
+            "Stopped after 1000000 instructions\n=> 0004: jmp $0"
+        );
+        assert_eq!(
+            run(&mut debugger, &mut vm, "continue"),
+            "Stopped after 1000000 instructions\n=> 0004: jmp $0"
+        );
+    }
+
+    #[test]
+    fn test_step_and_next() {
+        let (mut debugger, mut vm) = get_test_debugger();
+        assert_eq!(
+            run(&mut debugger, &mut vm, "step"),
+            "=> 0004 <main+4>: load $1 #12"
+        );
+        run(&mut debugger, &mut vm, "step");
+        assert_eq!(
+            run(&mut debugger, &mut vm, "next"),
+            "=> 000b <main+11>: hlt"
+        );
+        assert_eq!(vm.registers()[0], 6);
+        assert_eq!(run(&mut debugger, &mut vm, "next"), "HLT encountered");
+    }
+
+    #[test]
+    fn test_breakpoint_and_backtrace() {
+        let (mut debugger, mut vm) = get_test_debugger();
+        assert_eq!(
+            run(&mut debugger, &mut vm, "break double"),
+            "Breakpoint set at 000c <double>"
+        );
+        assert_eq!(
+            run(&mut debugger, &mut vm, "continue"),
+            "Breakpoint at 000c <double>\n=> 000c <double>: add $0 $0 $0"
+        );
+        assert_eq!(
+            run(&mut debugger, &mut vm, "backtrace"),
+            "#0 000c <double>\n#1 000b <main+11>"
+        );
+        assert_eq!(run(&mut debugger, &mut vm, "continue"), "HLT encountered");
+        assert_eq!(
+            run(&mut debugger, &mut vm, "break nowhere"),
+            "unknown label `nowhere`"
+        );
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let (mut debugger, mut vm) = get_test_debugger();
+        run(&mut debugger, &mut vm, "watch $0");
+        assert_eq!(
+            run(&mut debugger, &mut vm, "continue"),
+            "$0 changed: 0 -> 3\n=> 0004 <main+4>: load $1 #12"
+        );
+        assert_eq!(
+            run(&mut debugger, &mut vm, "continue"),
+            "$0 changed: 3 -> 6\n=> 0010 <double+4>: ret"
+        );
+
+        let mut debugger = Debugger::default();
+        let mut vm = VM::with_program(vec![0, 0, 0, 8, 17, 0, 0, 1, 0, 2, 23, 1, 0]);
+        run(&mut debugger, &mut vm, "watch heap[2..4]");
+        assert_eq!(
+            run(&mut debugger, &mut vm, "continue"),
+            "heap[2..4] changed: unallocated -> [0, 0]\n=> 0006: load $1 #2"
+        );
+        assert_eq!(
+            run(&mut debugger, &mut vm, "continue"),
+            "heap[2..4] changed: [0, 0] -> [8, 0]\n=> 000d: end of program"
+        );
+    }
+
+    #[test]
+    fn test_disasm_here() {
+        let (mut debugger, mut vm) = get_test_debugger();
+        run(&mut debugger, &mut vm, "step");
+        assert_eq!(
+            run(&mut debugger, &mut vm, "disasm here"),
+            "=> 0004 <main+4>: load $1 #12\n   0008 <main+8>: call #12\n   \
+             000b <main+11>: hlt\n   000c <double>: add $0 $0 $0\n   0010 <double+4>: ret"
+        );
+        assert_eq!(run(&mut debugger, &mut vm, "pc"), "0004 <main+4>");
+    }
+}
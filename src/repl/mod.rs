@@ -1,22 +1,38 @@
+mod debugger;
+
 use std::io;
-use std::num::ParseIntError;
+use std::path::Path;
 
-use nom::types::CompleteStr;
+use virian::disassembler::listing;
+use virian::verifier::verify;
+use virian::{Assembler, ExitReason, VM};
 
-use crate::assembler::program_parser::program;
-use crate::VM;
+use self::debugger::{parse_command, Debugger};
+use crate::cli::load_file;
 
 #[derive(Debug)]
-pub struct REPL {
+pub struct Repl {
     vm: VM,
     commands_buffer: Vec<String>,
+    debugger: Debugger,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl::new()
+    }
 }
 
-impl REPL {
+impl Repl {
     pub fn new() -> Self {
-        REPL {
-            vm: VM::new(),
+        Repl {
+            vm: {
+                let mut vm = VM::new();
+                vm.register_default_host_fns();
+                vm
+            },
             commands_buffer: vec![],
+            debugger: Debugger::default(),
         }
     }
 
@@ -31,7 +47,7 @@ impl REPL {
             let buffer = buffer.trim();
             self.commands_buffer.push(buffer.to_string());
 
-            match buffer.as_ref() {
+            match buffer {
                 "history" => {
                     for item in &self.commands_buffer {
                         println!("{}", &item);
@@ -39,22 +55,81 @@ impl REPL {
                 }
                 "registers" => {
                     println!("Listing registers and all contents:");
-                    println!("{:#?}", self.vm.registers);
+                    println!("{:#?}", self.vm.registers());
+                    println!("{:#?}", self.vm.float_registers());
+                    println!("Flags: {}", self.vm.flags());
                     println!("End of Register Listing")
                 }
+                "disasm" => {
+                    print!(
+                        "{}",
+                        listing(self.vm.program(), self.vm.ro_data(), None, true)
+                    );
+                }
                 "quit" | "q" => {
                     println!("Farewell! Have a great day!");
                     std::process::exit(0);
                 }
+                _ if buffer.starts_with("debug ") => {
+                    self.debug(Path::new(buffer["debug ".len()..].trim()));
+                }
                 _ => {
-                    let (_, results) = program(CompleteStr(buffer)).unwrap();
-                    let bytes = results.to_bytes();
+                    if let Some(command) = parse_command(buffer) {
+                        match command {
+                            Ok(command) => {
+                                println!("{}", self.debugger.execute(&mut self.vm, command))
+                            }
+                            Err(usage) => println!("{}", usage),
+                        }
+                        continue;
+                    }
+                    // Labels resolve to where the line ends up, after the code entered so far
+                    let mut assembler = Assembler::with_code_offset(self.vm.program().len() as u32);
+                    let bytes = match assembler.assemble_code(buffer) {
+                        Ok(bytes) => bytes,
+                        Err(errors) => {
+                            for e in errors {
+                                println!("{}", e);
+                            }
+                            continue;
+                        }
+                    };
+                    let mut candidate = self.vm.program().to_vec();
+                    candidate.extend_from_slice(&bytes);
+                    let diagnostics = verify(&candidate);
+                    if !diagnostics.is_empty() {
+                        for diagnostic in diagnostics {
+                            println!("Rejected: {}", diagnostic);
+                        }
+                        continue;
+                    }
                     for byte in bytes {
                         self.vm.add_byte(byte)
                     }
-                    self.vm.run_once();
+                    match self.vm.run_once() {
+                        Ok(ExitReason::Halted) => println!("HLT encountered"),
+                        Ok(ExitReason::Exited(code)) => println!("Exited with code {}", code),
+                        Ok(_) => {}
+                        Err(e) => println!("VM fault: {}", e),
+                    }
                 }
             }
         }
     }
+
+    /// Loads a program from a bytecode or assembly file to step through with the debugger
+    /// commands, replacing whatever was typed in before.
+    fn debug(&mut self, path: &Path) {
+        let mut file = match load_file(path) {
+            Ok(file) => file,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        self.debugger.load_symbols(file.symbols.take());
+        self.vm.reset();
+        self.vm.load_file(file);
+        println!("Loaded {}", path.display());
+    }
 }
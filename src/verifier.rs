@@ -0,0 +1,272 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::instructions::Opcode;
+use crate::vm::REGISTER_COUNT;
+
+/// Problem found in a byte stream before it is executed.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DiagnosticKind {
+    /// The byte does not map to an executable opcode.
+    IllegalOpcode(u8),
+    /// A register operand names a register past the end of the register file.
+    RegisterOutOfRange(u8),
+    /// The program ends partway through the instruction.
+    TruncatedInstruction,
+    /// A jump with a known target does not land on an instruction boundary.
+    InvalidJumpTarget(i64),
+}
+
+/// A verifier finding together with the offset of the offending instruction.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub offset: usize,
+    pub kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    pub fn new(offset: usize, kind: DiagnosticKind) -> Self {
+        Diagnostic { offset, kind }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "offset {}: ", self.offset)?;
+        match self.kind {
+            DiagnosticKind::IllegalOpcode(byte) => write!(f, "illegal opcode {:#04x}", byte),
+            DiagnosticKind::RegisterOutOfRange(reg) => write!(f, "register ${} out of range", reg),
+            DiagnosticKind::TruncatedInstruction => write!(f, "truncated instruction"),
+            DiagnosticKind::InvalidJumpTarget(target) => {
+                write!(f, "jump target {} is not an instruction boundary", target)
+            }
+        }
+    }
+}
+
+/// Walks `program` instruction by instruction and reports everything that would fault at run time
+/// regardless of register contents. An empty result means the program is safe to hand to the VM.
+pub fn verify(program: &[u8]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut instructions = vec![];
+    let mut boundaries = HashSet::new();
+
+    let mut offset = 0;
+    while offset < program.len() {
+        let byte = program[offset];
+        let opcode = Opcode::from(byte);
+        boundaries.insert(offset);
+
+        if opcode == Opcode::IGL {
+            diagnostics.push(Diagnostic::new(offset, DiagnosticKind::IllegalOpcode(byte)));
+            offset += 1;
+            continue;
+        }
+        if offset + opcode.width() > program.len() {
+            diagnostics.push(Diagnostic::new(
+                offset,
+                DiagnosticKind::TruncatedInstruction,
+            ));
+            break;
+        }
+
+        let encoding = opcode.encoding();
+        let operands = encoding.decode(&program[offset + 1..offset + encoding.width]);
+        for (kind, &value) in encoding.operands.iter().zip(&operands) {
+            if kind.is_register() && value > kind.range().1 {
+                diagnostics.push(Diagnostic::new(
+                    offset,
+                    DiagnosticKind::RegisterOutOfRange(value as u8),
+                ));
+            }
+        }
+        instructions.push((offset, opcode.clone(), operands));
+        offset += opcode.width();
+    }
+    boundaries.insert(program.len());
+
+    // Jump targets are only known when the register was loaded with a constant earlier in the same
+    // straight-line block, so the tracked values are dropped after every control-flow instruction.
+    let mut known = [None; REGISTER_COUNT];
+    for (offset, opcode, operands) in instructions {
+        let next = (offset + opcode.width()) as i64;
+        let known_value = |index: usize| known.get(operands[index] as usize).copied().flatten();
+        let target = match opcode {
+            Opcode::JMP
+            | Opcode::JEQ
+            | Opcode::JNEQ
+            | Opcode::JZ
+            | Opcode::JNZ
+            | Opcode::JC
+            | Opcode::JNC
+            | Opcode::JO
+            | Opcode::JNO
+            | Opcode::JN
+            | Opcode::JNN => known_value(0),
+            Opcode::JMPF => known_value(0).map(|value| next + value),
+            Opcode::JMPB => known_value(0).map(|value| next - value),
+            Opcode::CALL => Some(operands[0]),
+            _ => None,
+        };
+        if let Some(target) = target {
+            if target < 0 || !boundaries.contains(&(target as usize)) {
+                diagnostics.push(Diagnostic::new(
+                    offset,
+                    DiagnosticKind::InvalidJumpTarget(target),
+                ));
+            }
+        }
+
+        match opcode {
+            Opcode::LOAD | Opcode::LOADI => {
+                if let Some(slot) = known.get_mut(operands[0] as usize) {
+                    *slot = Some(operands[1]);
+                }
+            }
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::DIV
+            | Opcode::MOD
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::SHL
+            | Opcode::SHR
+            | Opcode::SAR => {
+                if let Some(slot) = known.get_mut(operands[2] as usize) {
+                    *slot = None;
+                }
+            }
+            Opcode::MOV => {
+                let value = known_value(0);
+                if let Some(slot) = known.get_mut(operands[1] as usize) {
+                    *slot = value;
+                }
+            }
+            Opcode::NOT | Opcode::FTOI => {
+                if let Some(slot) = known.get_mut(operands[1] as usize) {
+                    *slot = None;
+                }
+            }
+            Opcode::REM
+            | Opcode::POP
+            | Opcode::LOADB
+            | Opcode::LOADW
+            | Opcode::LOADCB
+            | Opcode::LOADCW => {
+                if let Some(slot) = known.get_mut(operands[0] as usize) {
+                    *slot = None;
+                }
+            }
+            Opcode::JMP
+            | Opcode::JMPF
+            | Opcode::JMPB
+            | Opcode::JEQ
+            | Opcode::JNEQ
+            | Opcode::JZ
+            | Opcode::JNZ
+            | Opcode::JC
+            | Opcode::JNC
+            | Opcode::JO
+            | Opcode::JNO
+            | Opcode::JN
+            | Opcode::JNN
+            | Opcode::CALL
+            | Opcode::RET
+            | Opcode::SYSCALL => {
+                known = [None; REGISTER_COUNT];
+            }
+            _ => {}
+        }
+    }
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.offset);
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_valid_program() {
+        let program = vec![0, 0, 0, 6, 5, 0, 6];
+        assert_eq!(verify(&program), vec![]);
+    }
+
+    #[test]
+    fn test_verify_illegal_opcode() {
+        let program = vec![6, 200, 6];
+        assert_eq!(
+            verify(&program),
+            vec![Diagnostic::new(1, DiagnosticKind::IllegalOpcode(200))]
+        );
+    }
+
+    #[test]
+    fn test_verify_register_out_of_range() {
+        let program = vec![1, 0, 40, 2];
+        assert_eq!(
+            verify(&program),
+            vec![Diagnostic::new(0, DiagnosticKind::RegisterOutOfRange(40))]
+        );
+    }
+
+    #[test]
+    fn test_verify_truncated_instruction() {
+        let program = vec![6, 0, 1];
+        assert_eq!(
+            verify(&program),
+            vec![Diagnostic::new(1, DiagnosticKind::TruncatedInstruction)]
+        );
+    }
+
+    #[test]
+    fn test_verify_misaligned_jump() {
+        let program = vec![0, 0, 0, 2, 5, 0, 6];
+        assert_eq!(
+            verify(&program),
+            vec![Diagnostic::new(4, DiagnosticKind::InvalidJumpTarget(2))]
+        );
+    }
+
+    #[test]
+    fn test_verify_relative_jump() {
+        let program = vec![0, 1, 0, 1, 7, 1, 6, 6];
+        assert_eq!(verify(&program), vec![]);
+
+        let program = vec![0, 1, 0, 9, 8, 1, 6];
+        assert_eq!(
+            verify(&program),
+            vec![Diagnostic::new(4, DiagnosticKind::InvalidJumpTarget(-3))]
+        );
+    }
+
+    #[test]
+    fn test_verify_call_target() {
+        let program = vec![20, 0, 4, 6, 21];
+        assert_eq!(verify(&program), vec![]);
+
+        let program = vec![20, 0, 2, 6, 21];
+        assert_eq!(
+            verify(&program),
+            vec![Diagnostic::new(0, DiagnosticKind::InvalidJumpTarget(2))]
+        );
+    }
+
+    #[test]
+    fn test_verify_forgets_overwritten_registers() {
+        let program = vec![0, 0, 0, 2, 1, 1, 1, 0, 5, 0, 6];
+        assert_eq!(verify(&program), vec![]);
+    }
+
+    #[test]
+    fn test_verify_follows_mov() {
+        let program = vec![0, 0, 0, 2, 39, 0, 3, 5, 3, 6];
+        assert_eq!(
+            verify(&program),
+            vec![Diagnostic::new(7, DiagnosticKind::InvalidJumpTarget(2))]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::error::Error;
+use std::fmt;
+
+/// Reason the VM stopped without faulting.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExitReason {
+    /// A `HLT` instruction was executed.
+    Halted,
+    /// The program counter ran past the last byte of the program.
+    EndOfProgram,
+    /// The VM can keep going. Returned by `run_once` after a single instruction and by
+    /// `run_until` when it reaches its limit.
+    Stepped,
+    /// A host function, such as the default `EXIT`, stopped the program with an exit code.
+    Exited(i32),
+    /// `run_with_fuel` ran out of fuel. The VM can be resumed with more.
+    OutOfFuel,
+}
+
+/// What went wrong while executing an instruction.
+#[derive(Debug, PartialEq, Clone)]
+pub enum VmErrorKind {
+    /// The opcode byte does not map to an executable instruction.
+    IllegalOpcode,
+    /// The program ends before all operands of the instruction could be read.
+    TruncatedInstruction,
+    /// An operand names a register past the end of the register file.
+    RegisterOutOfRange(u8),
+    /// A jump would move the program counter outside of the program.
+    InvalidJumpTarget(i64),
+    /// `DIV` with a zero divisor.
+    DivisionByZero,
+    /// SHRINK by a negative amount or by more than the heap holds.
+    HeapOverflow,
+    /// A heap load or store touches bytes past the end of the heap.
+    HeapOutOfBounds(i64),
+    /// A constant load reads past the end of the read-only data segment.
+    DataOutOfBounds(i64),
+    /// PUSH or CALL on a full stack.
+    StackOverflow,
+    /// POP or RET on an empty stack.
+    StackUnderflow,
+    /// `SYSCALL` with an id no host function is registered for.
+    UnknownHostFunction(u16),
+    /// A host function failed, for example because writing to standard output did.
+    HostError(String),
+}
+
+/// A fault raised while executing the instruction at `pc`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct VmError {
+    pub kind: VmErrorKind,
+
+    //Offset of the faulting instruction
+    pub pc: usize,
+
+    //Raw opcode byte of the faulting instruction
+    pub opcode: u8,
+}
+
+impl VmError {
+    pub fn new(kind: VmErrorKind, pc: usize, opcode: u8) -> Self {
+        VmError { kind, pc, opcode }
+    }
+}
+
+impl fmt::Display for VmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmErrorKind::IllegalOpcode => write!(f, "illegal opcode"),
+            VmErrorKind::TruncatedInstruction => write!(f, "truncated instruction"),
+            VmErrorKind::RegisterOutOfRange(reg) => write!(f, "register ${} out of range", reg),
+            VmErrorKind::InvalidJumpTarget(target) => write!(f, "invalid jump target {}", target),
+            VmErrorKind::DivisionByZero => write!(f, "division by zero"),
+            VmErrorKind::HeapOverflow => write!(f, "heap overflow"),
+            VmErrorKind::HeapOutOfBounds(address) => {
+                write!(f, "heap access out of bounds at {}", address)
+            }
+            VmErrorKind::DataOutOfBounds(address) => {
+                write!(f, "read-only data access out of bounds at {}", address)
+            }
+            VmErrorKind::StackOverflow => write!(f, "stack overflow"),
+            VmErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            VmErrorKind::UnknownHostFunction(id) => write!(f, "unknown host function {}", id),
+            VmErrorKind::HostError(message) => write!(f, "host function failed: {}", message),
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at pc {} (opcode {:#04x})",
+            self.kind, self.pc, self.opcode
+        )
+    }
+}
+
+impl Error for VmError {}
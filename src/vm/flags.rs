@@ -0,0 +1,59 @@
+use std::fmt;
+
+/// Status bits set by the last arithmetic or bitwise instruction.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Flags {
+    /// The result was 0.
+    pub zero: bool,
+    /// The unsigned result did not fit in 32 bits: a carry out of ADD, a borrow out of SUB. Also
+    /// set by an ALOC that failed.
+    pub carry: bool,
+    /// The signed result did not fit in 32 bits.
+    pub overflow: bool,
+    /// The result has its sign bit set.
+    pub negative: bool,
+}
+
+impl Flags {
+    /// Flags for `result`, with carry and overflow as given.
+    pub fn new(result: i32, carry: bool, overflow: bool) -> Self {
+        Flags {
+            zero: result == 0,
+            carry,
+            overflow,
+            negative: result < 0,
+        }
+    }
+
+    /// Flags for an instruction that can neither carry nor overflow.
+    pub fn from_result(result: i32) -> Self {
+        Flags::new(result, false, false)
+    }
+}
+
+// Set flags are shown by their letter and cleared ones by `-`, e.g. `Z--N`
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(set, letter) in &[
+            (self.zero, 'Z'),
+            (self.carry, 'C'),
+            (self.overflow, 'O'),
+            (self.negative, 'N'),
+        ] {
+            write!(f, "{}", if set { letter } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_flags() {
+        assert_eq!(Flags::default().to_string(), "----");
+        assert_eq!(Flags::new(0, true, false).to_string(), "ZC--");
+        assert_eq!(Flags::new(-3, false, true).to_string(), "--ON");
+    }
+}
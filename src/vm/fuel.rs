@@ -0,0 +1,59 @@
+use crate::instructions::Opcode;
+
+/// Lowest cost an opcode can have. Free instructions would let a loop run without using fuel.
+const MIN_COST: u64 = 1;
+
+/// Fuel `VM::run_with_fuel` charges for each opcode before executing it. Every opcode costs at
+/// least 1, so a program cannot run forever on a finite amount of fuel.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CostTable {
+    costs: [u64; 256],
+}
+
+impl Default for CostTable {
+    /// Every instruction costs 1, so fuel counts executed instructions.
+    fn default() -> Self {
+        CostTable::uniform(1)
+    }
+}
+
+impl CostTable {
+    /// Table charging `cost` for every opcode, or 1 if `cost` is 0.
+    pub fn uniform(cost: u64) -> Self {
+        CostTable {
+            costs: [cost.max(MIN_COST); 256],
+        }
+    }
+
+    pub fn cost(&self, opcode: &Opcode) -> u64 {
+        self.costs[opcode.clone() as usize]
+    }
+
+    /// Sets the cost of `opcode`, raised to 1 if `cost` is 0.
+    pub fn set_cost(&mut self, opcode: &Opcode, cost: u64) {
+        self.costs[opcode.clone() as usize] = cost.max(MIN_COST);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_table() {
+        let mut costs = CostTable::default();
+        assert_eq!(costs.cost(&Opcode::DIV), 1);
+        costs.set_cost(&Opcode::DIV, 20);
+        assert_eq!(costs.cost(&Opcode::DIV), 20);
+        assert_eq!(costs.cost(&Opcode::MUL), 1);
+        assert_eq!(CostTable::uniform(3).cost(&Opcode::IGL), 3);
+    }
+
+    #[test]
+    fn test_zero_cost_is_raised() {
+        let mut costs = CostTable::uniform(0);
+        assert_eq!(costs.cost(&Opcode::JMP), 1);
+        costs.set_cost(&Opcode::ADD, 0);
+        assert_eq!(costs.cost(&Opcode::ADD), 1);
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::vm::{ExitReason, VmErrorKind, VM};
+
+/// Function the program can call with `syscall #id`. It gets full access to the VM and returns
+/// `ExitReason::Stepped` to let the program continue, or any other reason to stop it.
+pub type HostFn = Box<dyn FnMut(&mut VM) -> Result<ExitReason, VmErrorKind>>;
+
+/// Prints `$0` as a decimal integer followed by a newline.
+pub const PRINT_INT: u16 = 0;
+/// Prints the NUL-terminated string at heap address `$0`.
+pub const PRINT_STRING: u16 = 1;
+/// Reads a line from standard input into the heap at `$0`, storing at most `$1` bytes without the
+/// line break. Leaves the number of bytes stored in `$0`, or -1 at the end of input.
+pub const READ_LINE: u16 = 2;
+/// Stops the program with `$0` as its exit code.
+pub const EXIT: u16 = 3;
+
+/// Host functions registered on a VM, keyed by id.
+#[derive(Default)]
+pub struct HostFns(HashMap<u16, HostFn>);
+
+impl HostFns {
+    pub fn insert(&mut self, id: u16, function: HostFn) {
+        self.0.insert(id, function);
+    }
+
+    pub fn remove(&mut self, id: u16) -> Option<HostFn> {
+        self.0.remove(&id)
+    }
+
+    /// Puts back a function taken out with `remove`, unless it was replaced in the meantime.
+    pub fn restore(&mut self, id: u16, function: HostFn) {
+        self.0.entry(id).or_insert(function);
+    }
+}
+
+impl fmt::Debug for HostFns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ids: Vec<&u16> = self.0.keys().collect();
+        ids.sort();
+        f.debug_set().entries(ids).finish()
+    }
+}
+
+/// Registers the default host functions, wired to standard input and output.
+pub fn register_defaults(vm: &mut VM) {
+    vm.register_host_fn(PRINT_INT, |vm: &mut VM| print_int(vm, &mut io::stdout()));
+    vm.register_host_fn(PRINT_STRING, |vm: &mut VM| {
+        print_string(vm, &mut io::stdout())
+    });
+    vm.register_host_fn(READ_LINE, |vm: &mut VM| {
+        read_line(vm, &mut io::stdin().lock())
+    });
+    vm.register_host_fn(EXIT, |vm: &mut VM| Ok(ExitReason::Exited(vm.registers[0])));
+}
+
+pub fn print_int(vm: &mut VM, out: &mut dyn Write) -> Result<ExitReason, VmErrorKind> {
+    writeln!(out, "{}", vm.registers[0]).map_err(host_error)?;
+    Ok(ExitReason::Stepped)
+}
+
+pub fn print_string(vm: &mut VM, out: &mut dyn Write) -> Result<ExitReason, VmErrorKind> {
+    let address = vm.registers[0];
+    let bytes = usize::try_from(address)
+        .ok()
+        .and_then(|start| vm.heap().get(start..))
+        .ok_or(VmErrorKind::HeapOutOfBounds(i64::from(address)))?;
+    let len = bytes
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(VmErrorKind::HeapOutOfBounds(i64::from(address)))?;
+    out.write_all(&bytes[..len]).map_err(host_error)?;
+    out.flush().map_err(host_error)?;
+    Ok(ExitReason::Stepped)
+}
+
+pub fn read_line(vm: &mut VM, input: &mut dyn BufRead) -> Result<ExitReason, VmErrorKind> {
+    let address = vm.registers[0];
+    let capacity = vm.registers[1].max(0) as usize;
+    let mut line = String::new();
+    if input.read_line(&mut line).map_err(host_error)? == 0 {
+        vm.registers[0] = -1;
+        return Ok(ExitReason::Stepped);
+    }
+    let line = line.trim_end_matches(['\n', '\r']).as_bytes();
+    let len = line.len().min(capacity);
+    let target = usize::try_from(address)
+        .ok()
+        .and_then(|start| vm.heap_mut().get_mut(start..start + len))
+        .ok_or(VmErrorKind::HeapOutOfBounds(i64::from(address)))?;
+    target.copy_from_slice(&line[..len]);
+    vm.registers[0] = len as i32;
+    Ok(ExitReason::Stepped)
+}
+
+fn host_error(e: io::Error) -> VmErrorKind {
+    VmErrorKind::HostError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vm_with_heap(heap: &[u8]) -> VM {
+        let mut vm = VM::new();
+        vm.heap.extend_from_slice(heap);
+        vm
+    }
+
+    #[test]
+    fn test_print_int() {
+        let mut vm = VM::new();
+        vm.registers[0] = -42;
+        let mut out = vec![];
+        assert_eq!(print_int(&mut vm, &mut out), Ok(ExitReason::Stepped));
+        assert_eq!(out, b"-42\n");
+    }
+
+    #[test]
+    fn test_print_string() {
+        let mut vm = vm_with_heap(b"xhi\0!");
+        vm.registers[0] = 1;
+        let mut out = vec![];
+        assert_eq!(print_string(&mut vm, &mut out), Ok(ExitReason::Stepped));
+        assert_eq!(out, b"hi");
+
+        vm.registers[0] = 4;
+        assert_eq!(
+            print_string(&mut vm, &mut out),
+            Err(VmErrorKind::HeapOutOfBounds(4))
+        );
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut vm = vm_with_heap(&[0; 8]);
+        vm.registers[0] = 2;
+        vm.registers[1] = 4;
+        let mut input = &b"hello\nworld\n"[..];
+        read_line(&mut vm, &mut input).unwrap();
+        assert_eq!(vm.registers[0], 4);
+        assert_eq!(vm.heap(), b"\0\0hell\0\0");
+
+        vm.registers[0] = 6;
+        vm.registers[1] = 8;
+        assert_eq!(
+            read_line(&mut vm, &mut input),
+            Err(VmErrorKind::HeapOutOfBounds(6))
+        );
+
+        vm.registers[0] = 0;
+        read_line(&mut vm, &mut input).unwrap();
+        assert_eq!(vm.registers[0], -1);
+    }
+}
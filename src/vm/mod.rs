@@ -0,0 +1,1613 @@
+use std::convert::TryFrom;
+use std::ops::Range;
+
+use crate::bytecode::{BytecodeError, BytecodeFile};
+use crate::instructions::Opcode;
+
+pub use self::error::{ExitReason, VmError, VmErrorKind};
+pub use self::flags::Flags;
+pub use self::fuel::CostTable;
+pub use self::host::HostFn;
+use self::host::HostFns;
+pub use self::snapshot::{SnapshotError, SNAPSHOT_MAGIC, SNAPSHOT_VERSION};
+pub use self::trace::{RegisterChange, TraceEvent, TraceFormat, TraceWriter, Tracer};
+
+mod error;
+mod flags;
+mod fuel;
+pub mod host;
+mod snapshot;
+mod trace;
+
+/// Maximum depth of both the value stack and the call stack.
+pub const STACK_LIMIT: usize = 1024;
+
+/// Number of general purpose registers, addressed as `$0` to `$31`.
+pub const REGISTER_COUNT: usize = 32;
+
+/// Heap size a new VM is limited to, 16 MiB.
+pub const DEFAULT_HEAP_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Number of float registers, addressed as `$f0` to `$f31`.
+pub const FLOAT_REGISTER_COUNT: usize = 32;
+
+#[derive(Debug)]
+pub struct VM {
+    //Array of registers simulating hardware registers
+    registers: [i32; REGISTER_COUNT],
+
+    //Float registers used by the F* instructions
+    float_registers: [f64; FLOAT_REGISTER_COUNT],
+
+    //Program counter tracks current program instruction byte executing
+    pc: usize,
+
+    //Bytes of the program
+    program: Vec<u8>,
+
+    //Heap Memory
+    heap: Vec<u8>,
+
+    //Size ALOC refuses to grow the heap past
+    heap_limit: usize,
+
+    //Read-only data segment emitted by the assembler's .data section
+    ro_data: Vec<u8>,
+
+    //Remainder value
+    remainder: u32,
+
+    //Equality check result of the last operation
+    equal: bool,
+
+    //Status bits of the last arithmetic or bitwise instruction
+    flags: Flags,
+
+    //Values pushed by PUSH and popped by POP
+    stack: Vec<i32>,
+
+    //Return addresses pushed by CALL and popped by RET
+    call_stack: Vec<usize>,
+
+    //Functions the program calls with SYSCALL
+    host_fns: HostFns,
+
+    //Fuel charged per opcode by run_with_fuel
+    costs: CostTable,
+
+    //Total fuel charged by run_with_fuel
+    fuel_used: u64,
+
+    //Called after every executed instruction when set
+    tracer: Option<Box<dyn Tracer>>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
+}
+
+impl VM {
+    pub fn new() -> Self {
+        VM {
+            registers: [0; REGISTER_COUNT],
+            float_registers: [0.0; FLOAT_REGISTER_COUNT],
+            pc: 0,
+            program: vec![],
+            heap: vec![],
+            heap_limit: DEFAULT_HEAP_LIMIT,
+            ro_data: vec![],
+            remainder: 0,
+            equal: false,
+            flags: Flags::default(),
+            stack: vec![],
+            call_stack: vec![],
+            host_fns: HostFns::default(),
+            costs: CostTable::default(),
+            fuel_used: 0,
+            tracer: None,
+        }
+    }
+
+    /// VM with `program` loaded and the program counter at its first byte.
+    pub fn with_program(program: Vec<u8>) -> Self {
+        let mut vm = VM::new();
+        vm.load_program(program);
+        vm
+    }
+
+    /// Replaces the program with raw bytecode, drops the read-only data and moves the program
+    /// counter to the start. Registers, heap and stacks are left as they are; see `reset`.
+    pub fn load_program(&mut self, program: Vec<u8>) {
+        self.program = program;
+        self.ro_data = vec![];
+        self.pc = 0;
+    }
+
+    /// Puts the VM back in the state it was created in, keeping the loaded program, read-only data,
+    /// host functions and tracer. The program counter goes back to 0.
+    pub fn reset(&mut self) {
+        self.registers = [0; REGISTER_COUNT];
+        self.float_registers = [0.0; FLOAT_REGISTER_COUNT];
+        self.pc = 0;
+        self.heap = vec![];
+        self.remainder = 0;
+        self.equal = false;
+        self.flags = Flags::default();
+        self.stack = vec![];
+        self.call_stack = vec![];
+        self.fuel_used = 0;
+    }
+
+    /// Loops as long as instructions can be executed.
+    pub fn run(&mut self) -> Result<ExitReason, VmError> {
+        loop {
+            match self.execute_instruction()? {
+                ExitReason::Stepped => {}
+                reason => return Ok(reason),
+            }
+        }
+    }
+
+    /// Like `run`, but executes at most `limit` instructions. Returns `ExitReason::Stepped` when
+    /// the limit is reached first, after which the program can be resumed with another call.
+    pub fn run_until(&mut self, limit: usize) -> Result<ExitReason, VmError> {
+        for _ in 0..limit {
+            match self.execute_instruction()? {
+                ExitReason::Stepped => {}
+                reason => return Ok(reason),
+            }
+        }
+        Ok(ExitReason::Stepped)
+    }
+
+    /// Like `run`, but stops with `ExitReason::OutOfFuel` before an instruction that costs more
+    /// than the fuel left of `fuel`. The instruction is not executed, so calling this again with
+    /// more fuel resumes the program where it stopped. Costs come from the VM's cost table.
+    pub fn run_with_fuel(&mut self, fuel: u64) -> Result<ExitReason, VmError> {
+        let mut fuel = fuel;
+        loop {
+            if let Some(&byte) = self.program.get(self.pc) {
+                let cost = self.costs.cost(&Opcode::from(byte));
+                if cost > fuel {
+                    return Ok(ExitReason::OutOfFuel);
+                }
+                fuel -= cost;
+                self.fuel_used += cost;
+            }
+            match self.execute_instruction()? {
+                ExitReason::Stepped => {}
+                reason => return Ok(reason),
+            }
+        }
+    }
+
+    pub fn run_once(&mut self) -> Result<ExitReason, VmError> {
+        self.execute_instruction()
+    }
+
+    fn execute_instruction(&mut self) -> Result<ExitReason, VmError> {
+        if self.pc >= self.program.len() {
+            return Ok(ExitReason::EndOfProgram);
+        }
+        let pc = self.pc;
+        let byte = self.program[pc];
+        let opcode = self.decode_opcode();
+        if pc + opcode.width() > self.program.len() {
+            return Err(VmError::new(VmErrorKind::TruncatedInstruction, pc, byte));
+        }
+        if self.tracer.is_none() {
+            return self
+                .step(opcode)
+                .map_err(|kind| VmError::new(kind, pc, byte));
+        }
+
+        let registers = self.registers;
+        let float_registers = self.float_registers;
+        let encoding = opcode.encoding();
+        let operands = encoding.decode(&self.program[pc + 1..pc + encoding.width]);
+        let operands = operands[..encoding.operands.len()].to_vec();
+        let result = self.step(opcode.clone());
+        let event = TraceEvent {
+            pc,
+            opcode,
+            operands,
+            changes: RegisterChange::between(
+                (&registers, &float_registers),
+                (&self.registers, &self.float_registers),
+            ),
+            result: result.clone(),
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(&event);
+        }
+        result.map_err(|kind| VmError::new(kind, pc, byte))
+    }
+
+    fn step(&mut self, opcode: Opcode) -> Result<ExitReason, VmErrorKind> {
+        let operands = self.decode_operands(&opcode)?;
+        match opcode {
+            Opcode::LOAD | Opcode::LOADI => {
+                self.registers[operands.register(0)] = operands.immediate(1);
+            }
+            Opcode::HLT => {
+                return Ok(ExitReason::Halted);
+            }
+            Opcode::ADD => {
+                let register1 = self.value(&operands, 0);
+                let register2 = self.value(&operands, 1);
+                let (result, overflow) = register1.overflowing_add(register2);
+                let (_, carry) = (register1 as u32).overflowing_add(register2 as u32);
+                self.set_result(&operands, 2, result, Flags::new(result, carry, overflow));
+            }
+            Opcode::SUB => {
+                let register1 = self.value(&operands, 0);
+                let register2 = self.value(&operands, 1);
+                let (result, overflow) = register1.overflowing_sub(register2);
+                let (_, carry) = (register1 as u32).overflowing_sub(register2 as u32);
+                self.set_result(&operands, 2, result, Flags::new(result, carry, overflow));
+            }
+            Opcode::MUL => {
+                let register1 = self.value(&operands, 0);
+                let register2 = self.value(&operands, 1);
+                let (result, overflow) = register1.overflowing_mul(register2);
+                self.set_result(&operands, 2, result, Flags::new(result, overflow, overflow));
+            }
+            Opcode::DIV => {
+                let register1 = self.value(&operands, 0);
+                let register2 = self.value(&operands, 1);
+                if register2 == 0 {
+                    return Err(VmErrorKind::DivisionByZero);
+                }
+                let (result, overflow) = register1.overflowing_div(register2);
+                self.remainder = register1.wrapping_rem(register2) as u32;
+                self.set_result(&operands, 2, result, Flags::new(result, false, overflow));
+            }
+            Opcode::MOD => {
+                let register1 = self.value(&operands, 0);
+                let register2 = self.value(&operands, 1);
+                if register2 == 0 {
+                    return Err(VmErrorKind::DivisionByZero);
+                }
+                let (result, overflow) = register1.overflowing_rem(register2);
+                self.set_result(&operands, 2, result, Flags::new(result, false, overflow));
+            }
+            Opcode::REM => {
+                self.registers[operands.register(0)] = self.remainder as i32;
+            }
+            Opcode::AND => {
+                let result = self.value(&operands, 0) & self.value(&operands, 1);
+                self.set_result(&operands, 2, result, Flags::from_result(result));
+            }
+            Opcode::OR => {
+                let result = self.value(&operands, 0) | self.value(&operands, 1);
+                self.set_result(&operands, 2, result, Flags::from_result(result));
+            }
+            Opcode::XOR => {
+                let result = self.value(&operands, 0) ^ self.value(&operands, 1);
+                self.set_result(&operands, 2, result, Flags::from_result(result));
+            }
+            Opcode::NOT => {
+                let result = !self.value(&operands, 0);
+                self.set_result(&operands, 1, result, Flags::from_result(result));
+            }
+            // Shift amounts are taken modulo 32, like the shift instructions of most CPUs
+            Opcode::SHL => {
+                let register1 = self.value(&operands, 0);
+                let register2 = self.value(&operands, 1);
+                let result = register1.wrapping_shl(register2 as u32);
+                self.set_result(&operands, 2, result, Flags::from_result(result));
+            }
+            Opcode::SHR => {
+                let register1 = self.value(&operands, 0) as u32;
+                let register2 = self.value(&operands, 1);
+                let result = register1.wrapping_shr(register2 as u32) as i32;
+                self.set_result(&operands, 2, result, Flags::from_result(result));
+            }
+            Opcode::SAR => {
+                let register1 = self.value(&operands, 0);
+                let register2 = self.value(&operands, 1);
+                let result = register1.wrapping_shr(register2 as u32);
+                self.set_result(&operands, 2, result, Flags::from_result(result));
+            }
+            Opcode::MOV => {
+                self.registers[operands.register(1)] = self.value(&operands, 0);
+            }
+            Opcode::JMP => {
+                let target = self.value(&operands, 0);
+                self.jump_to(i64::from(target))?;
+            }
+            Opcode::JMPF => {
+                let target = self.value(&operands, 0);
+                self.jump_to(self.pc as i64 + i64::from(target))?;
+            }
+            Opcode::JMPB => {
+                let target = self.value(&operands, 0);
+                self.jump_to(self.pc as i64 - i64::from(target))?;
+            }
+            // Comparisons are signed: -1 is less than 0. They used to compare the registers cast
+            // to usize, which ordered every negative value above every positive one.
+            Opcode::EQ => {
+                self.equal = self.value(&operands, 0) == self.value(&operands, 1);
+            }
+            Opcode::NEQ => {
+                self.equal = self.value(&operands, 0) != self.value(&operands, 1);
+            }
+            Opcode::LT => {
+                self.equal = self.value(&operands, 0) < self.value(&operands, 1);
+            }
+            Opcode::LTQ => {
+                self.equal = self.value(&operands, 0) <= self.value(&operands, 1);
+            }
+            Opcode::GT => {
+                self.equal = self.value(&operands, 0) > self.value(&operands, 1);
+            }
+            Opcode::GTQ => {
+                self.equal = self.value(&operands, 0) >= self.value(&operands, 1);
+            }
+            Opcode::JEQ => {
+                let target = self.value(&operands, 0);
+
+                if self.equal {
+                    self.jump_to(i64::from(target))?;
+                }
+            }
+            Opcode::JNEQ => {
+                let target = self.value(&operands, 0);
+
+                if !self.equal {
+                    self.jump_to(i64::from(target))?;
+                }
+            }
+            Opcode::JZ => self.jump_if(self.flags.zero, &operands)?,
+            Opcode::JNZ => self.jump_if(!self.flags.zero, &operands)?,
+            Opcode::JC => self.jump_if(self.flags.carry, &operands)?,
+            Opcode::JNC => self.jump_if(!self.flags.carry, &operands)?,
+            Opcode::JO => self.jump_if(self.flags.overflow, &operands)?,
+            Opcode::JNO => self.jump_if(!self.flags.overflow, &operands)?,
+            Opcode::JN => self.jump_if(self.flags.negative, &operands)?,
+            Opcode::JNN => self.jump_if(!self.flags.negative, &operands)?,
+            // Float arithmetic follows IEEE 754 and leaves the flags alone, so dividing by zero
+            // gives an infinity or NaN rather than a fault
+            Opcode::LOADF => {
+                self.float_registers[operands.register(0)] = operands.float(1);
+            }
+            Opcode::FADD => {
+                let register1 = self.float_value(&operands, 0);
+                let register2 = self.float_value(&operands, 1);
+                self.float_registers[operands.register(2)] = register1 + register2;
+            }
+            Opcode::FSUB => {
+                let register1 = self.float_value(&operands, 0);
+                let register2 = self.float_value(&operands, 1);
+                self.float_registers[operands.register(2)] = register1 - register2;
+            }
+            Opcode::FMUL => {
+                let register1 = self.float_value(&operands, 0);
+                let register2 = self.float_value(&operands, 1);
+                self.float_registers[operands.register(2)] = register1 * register2;
+            }
+            Opcode::FDIV => {
+                let register1 = self.float_value(&operands, 0);
+                let register2 = self.float_value(&operands, 1);
+                self.float_registers[operands.register(2)] = register1 / register2;
+            }
+            Opcode::FEQ => {
+                self.equal = self.float_value(&operands, 0) == self.float_value(&operands, 1);
+            }
+            Opcode::FNEQ => {
+                self.equal = self.float_value(&operands, 0) != self.float_value(&operands, 1);
+            }
+            Opcode::FLT => {
+                self.equal = self.float_value(&operands, 0) < self.float_value(&operands, 1);
+            }
+            Opcode::FLTQ => {
+                self.equal = self.float_value(&operands, 0) <= self.float_value(&operands, 1);
+            }
+            Opcode::FGT => {
+                self.equal = self.float_value(&operands, 0) > self.float_value(&operands, 1);
+            }
+            Opcode::FGTQ => {
+                self.equal = self.float_value(&operands, 0) >= self.float_value(&operands, 1);
+            }
+            Opcode::ITOF => {
+                self.float_registers[operands.register(1)] = f64::from(self.value(&operands, 0));
+            }
+            // Rounds toward zero and saturates at the ends of the i32 range, NaN converts to 0
+            Opcode::FTOI => {
+                self.registers[operands.register(1)] = self.float_value(&operands, 0) as i32;
+            }
+            // Failure is reported in the carry flag rather than as a fault, so the program can
+            // react to running out of memory
+            Opcode::ALOC => {
+                let bytes = self.value(&operands, 0);
+                let new_len = usize::try_from(bytes)
+                    .ok()
+                    .and_then(|bytes| self.heap.len().checked_add(bytes))
+                    .filter(|&new_len| new_len <= self.heap_limit);
+                if let Some(new_len) = new_len {
+                    self.heap.resize(new_len, 0);
+                }
+                self.flags.carry = new_len.is_none();
+            }
+            Opcode::LOADB => {
+                let address = self.value(&operands, 1);
+                let range = self.heap_range(address, 1)?;
+                self.registers[operands.register(0)] = i32::from(self.heap[range.start]);
+            }
+            Opcode::STOREB => {
+                let address = self.value(&operands, 0);
+                let value = self.value(&operands, 1);
+                let range = self.heap_range(address, 1)?;
+                self.heap[range.start] = value as u8;
+            }
+            Opcode::LOADW => {
+                let address = self.value(&operands, 1);
+                let range = self.heap_range(address, 4)?;
+                let mut word = [0; 4];
+                word.copy_from_slice(&self.heap[range]);
+                self.registers[operands.register(0)] = i32::from_be_bytes(word);
+            }
+            Opcode::STOREW => {
+                let address = self.value(&operands, 0);
+                let value = self.value(&operands, 1);
+                let range = self.heap_range(address, 4)?;
+                self.heap[range].copy_from_slice(&value.to_be_bytes());
+            }
+            Opcode::LOADCB => {
+                let address = self.value(&operands, 1);
+                let range = self.ro_data_range(address, 1)?;
+                self.registers[operands.register(0)] = i32::from(self.ro_data[range.start]);
+            }
+            Opcode::LOADCW => {
+                let address = self.value(&operands, 1);
+                let range = self.ro_data_range(address, 4)?;
+                let mut word = [0; 4];
+                word.copy_from_slice(&self.ro_data[range]);
+                self.registers[operands.register(0)] = i32::from_be_bytes(word);
+            }
+            Opcode::FREE => {
+                self.heap.clear();
+            }
+            Opcode::SHRINK => {
+                let bytes = self.value(&operands, 0);
+                if bytes < 0 || bytes as usize > self.heap.len() {
+                    return Err(VmErrorKind::HeapOverflow);
+                }
+                self.heap.truncate(self.heap.len() - bytes as usize);
+            }
+            Opcode::PUSH => {
+                let value = self.value(&operands, 0);
+                if self.stack.len() >= STACK_LIMIT {
+                    return Err(VmErrorKind::StackOverflow);
+                }
+                self.stack.push(value);
+            }
+            Opcode::POP => {
+                let value = self.stack.pop().ok_or(VmErrorKind::StackUnderflow)?;
+                self.registers[operands.register(0)] = value;
+            }
+            Opcode::CALL => {
+                let target = operands.immediate(0);
+                if self.call_stack.len() >= STACK_LIMIT {
+                    return Err(VmErrorKind::StackOverflow);
+                }
+                let return_address = self.pc;
+                self.jump_to(i64::from(target))?;
+                self.call_stack.push(return_address);
+            }
+            Opcode::SYSCALL => {
+                return self.call_host_fn(operands.immediate(0) as u16);
+            }
+            Opcode::RET => {
+                let return_address = self.call_stack.pop().ok_or(VmErrorKind::StackUnderflow)?;
+                self.pc = return_address;
+            }
+            Opcode::IGL => {
+                return Err(VmErrorKind::IllegalOpcode);
+            }
+        }
+        Ok(ExitReason::Stepped)
+    }
+
+    fn decode_opcode(&mut self) -> Opcode {
+        let opcode = Opcode::from(self.program[self.pc]);
+        self.pc += 1;
+        opcode
+    }
+
+    /// Decodes the operands that follow the opcode byte and moves `pc` past the instruction,
+    /// padding included. Register operands are checked against the register file.
+    fn decode_operands(&mut self, opcode: &Opcode) -> Result<Operands, VmErrorKind> {
+        let encoding = opcode.encoding();
+        let end = self.pc + encoding.width - 1;
+        let bytes = self
+            .program
+            .get(self.pc..end)
+            .ok_or(VmErrorKind::TruncatedInstruction)?;
+        let operands = encoding.decode(bytes);
+        for (kind, &value) in encoding.operands.iter().zip(&operands) {
+            if kind.is_register() && value > kind.range().1 {
+                return Err(VmErrorKind::RegisterOutOfRange(value as u8));
+            }
+        }
+        self.pc = end;
+        Ok(Operands(operands))
+    }
+
+    /// Writes the result of an arithmetic or bitwise instruction to the register named by operand
+    /// `index` and replaces the flags.
+    fn set_result(&mut self, operands: &Operands, index: usize, result: i32, flags: Flags) {
+        self.registers[operands.register(index)] = result;
+        self.flags = flags;
+    }
+
+    /// Runs the host function registered for `id`. It is taken out of the table while it runs so
+    /// it can be handed the VM.
+    fn call_host_fn(&mut self, id: u16) -> Result<ExitReason, VmErrorKind> {
+        let mut function = self
+            .host_fns
+            .remove(id)
+            .ok_or(VmErrorKind::UnknownHostFunction(id))?;
+        let result = function(self);
+        self.host_fns.restore(id, function);
+        result
+    }
+
+    /// Contents of the register named by operand `index`.
+    fn value(&self, operands: &Operands, index: usize) -> i32 {
+        self.registers[operands.register(index)]
+    }
+
+    /// Contents of the float register named by operand `index`.
+    fn float_value(&self, operands: &Operands, index: usize) -> f64 {
+        self.float_registers[operands.register(index)]
+    }
+
+    /// Byte range of `len` bytes starting at `address`, checked against the current heap size.
+    fn heap_range(&self, address: i32, len: usize) -> Result<Range<usize>, VmErrorKind> {
+        if address < 0 || address as usize + len > self.heap.len() {
+            return Err(VmErrorKind::HeapOutOfBounds(i64::from(address)));
+        }
+        Ok(address as usize..address as usize + len)
+    }
+
+    /// Byte range of `len` bytes starting at `address`, checked against the read-only data segment.
+    fn ro_data_range(&self, address: i32, len: usize) -> Result<Range<usize>, VmErrorKind> {
+        if address < 0 || address as usize + len > self.ro_data.len() {
+            return Err(VmErrorKind::DataOutOfBounds(i64::from(address)));
+        }
+        Ok(address as usize..address as usize + len)
+    }
+
+    /// Jumps to the address in the register named by the first operand if `condition` holds.
+    fn jump_if(&mut self, condition: bool, operands: &Operands) -> Result<(), VmErrorKind> {
+        if condition {
+            self.jump_to(i64::from(self.value(operands, 0)))?;
+        }
+        Ok(())
+    }
+
+    /// Moves the program counter, refusing targets outside of the program.
+    fn jump_to(&mut self, target: i64) -> Result<(), VmErrorKind> {
+        if target < 0 || target as usize > self.program.len() {
+            return Err(VmErrorKind::InvalidJumpTarget(target));
+        }
+        self.pc = target as usize;
+        Ok(())
+    }
+
+    pub fn registers(&self) -> &[i32; REGISTER_COUNT] {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [i32; REGISTER_COUNT] {
+        &mut self.registers
+    }
+
+    pub fn float_registers(&self) -> &[f64; FLOAT_REGISTER_COUNT] {
+        &self.float_registers
+    }
+
+    pub fn float_registers_mut(&mut self) -> &mut [f64; FLOAT_REGISTER_COUNT] {
+        &mut self.float_registers
+    }
+
+    /// Calls `tracer` for every instruction the VM executes from now on, replacing the tracer set
+    /// before. The event holds what was decoded before the instruction ran and how it ended, so
+    /// an instruction that faults is traced too. Only an instruction cut off by the end of the
+    /// program is not, since its operands cannot be decoded.
+    pub fn set_tracer<T: Tracer + 'static>(&mut self, tracer: T) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    /// Stops tracing and hands back the tracer, e.g. to `flush` it.
+    pub fn remove_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        self.tracer.take()
+    }
+
+    /// Offset of the next instruction to execute.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// Return addresses of the calls in progress, innermost last.
+    pub fn call_stack(&self) -> &[usize] {
+        &self.call_stack
+    }
+
+    /// Makes `function` callable from the program as `syscall #id`, replacing any function
+    /// registered for `id` before.
+    pub fn register_host_fn<F>(&mut self, id: u16, function: F)
+    where
+        F: FnMut(&mut VM) -> Result<ExitReason, VmErrorKind> + 'static,
+    {
+        self.host_fns.insert(id, Box::new(function));
+    }
+
+    /// Registers the host functions in `host`: printing integers and strings, reading a line and
+    /// exiting with a code.
+    pub fn register_default_host_fns(&mut self) {
+        host::register_defaults(self);
+    }
+
+    pub fn cost_table(&self) -> &CostTable {
+        &self.costs
+    }
+
+    /// Replaces the fuel costs `run_with_fuel` charges per opcode.
+    pub fn set_cost_table(&mut self, costs: CostTable) {
+        self.costs = costs;
+    }
+
+    /// Fuel charged by `run_with_fuel` since the VM was created or reset.
+    pub fn fuel_used(&self) -> u64 {
+        self.fuel_used
+    }
+
+    pub fn heap_limit(&self) -> usize {
+        self.heap_limit
+    }
+
+    /// Sets the size ALOC may grow the heap to. A heap already larger than `limit` is left alone,
+    /// but can not grow until it has been shrunk below it.
+    pub fn set_heap_limit(&mut self, limit: usize) {
+        self.heap_limit = limit;
+    }
+
+    pub fn heap(&self) -> &[u8] {
+        &self.heap
+    }
+
+    /// The heap for host functions to write to. Its size is only changed by the program.
+    pub fn heap_mut(&mut self) -> &mut [u8] {
+        &mut self.heap
+    }
+
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+
+    pub fn ro_data(&self) -> &[u8] {
+        &self.ro_data
+    }
+
+    /// Replaces the read-only data segment addressed by LOADCB and LOADCW.
+    pub fn set_ro_data(&mut self, data: Vec<u8>) {
+        self.ro_data = data;
+    }
+
+    /// Loads a bytecode file, replacing the program and read-only data and moving the program
+    /// counter to the entry point. Files with the wrong magic number or version are refused.
+    pub fn load_bytecode(&mut self, bytes: &[u8]) -> Result<(), BytecodeError> {
+        let file = BytecodeFile::from_bytes(bytes)?;
+        self.load_file(file);
+        Ok(())
+    }
+
+    /// Loads an already parsed bytecode file, see `load_bytecode`.
+    pub fn load_file(&mut self, file: BytecodeFile) {
+        self.program = file.code;
+        self.ro_data = file.data;
+        self.pc = file.entry_point as usize;
+    }
+
+    pub fn add_byte(&mut self, byte: u8) {
+        self.program.push(byte);
+    }
+}
+
+/// Operands of the instruction being executed, decoded with its opcode's encoding.
+struct Operands([i64; 3]);
+
+impl Operands {
+    /// Register named by operand `index`, already checked against the register file.
+    fn register(&self, index: usize) -> usize {
+        self.0[index] as usize
+    }
+
+    fn immediate(&self, index: usize) -> i32 {
+        self.0[index] as i32
+    }
+
+    fn float(&self, index: usize) -> f64 {
+        f64::from_bits(self.0[index] as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_vm() -> VM {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = 5;
+        test_vm.registers[1] = 10;
+        test_vm
+    }
+
+    #[test]
+    fn test_create_vm() {
+        let test_vm = VM::new();
+        assert_eq!(test_vm.registers[0], 0)
+    }
+
+    #[test]
+    fn test_opcode_hlt() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![6, 0, 0, 0];
+        test_vm.program = test_bytes;
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_opcode_igl() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![200, 0, 0, 0];
+        test_vm.program = test_bytes;
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::IllegalOpcode, 0, 200))
+        );
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_load_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![0, 0, 1, 244]; // Remember, this is how we represent 500 using two u8s in little endian format
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.registers[0], 500);
+    }
+
+    #[test]
+    fn test_loadi_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![30, 0, 255, 255, 255, 251, 30, 1, 0, 1, 17, 112];
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.registers[0], -5);
+        assert_eq!(test_vm.registers[1], 70000);
+    }
+
+    #[test]
+    fn test_jmp_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = 1;
+        test_vm.program = vec![5, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_jmpf_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = 2;
+        test_vm.program = vec![7, 0, 0, 0, 6, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpb_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.pc = 8;
+        test_vm.registers[0] = 4;
+        test_vm.program = vec![0, 0, 0, 4, 0, 1, 0, 2, 8, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 6);
+    }
+
+    #[test]
+    fn test_eq_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![9, 0, 1, 0, 9, 0, 1, 0];
+        test_vm.run_once().unwrap();
+        assert!(test_vm.equal);
+        test_vm.registers[1] = 20;
+        test_vm.run_once().unwrap();
+        assert!(!test_vm.equal);
+    }
+
+    #[test]
+    fn test_comparisons_are_signed() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = -1;
+        test_vm.registers[1] = 0;
+        for &(opcode, expected) in &[(11, true), (12, true), (13, false), (14, false)] {
+            test_vm.program = vec![opcode, 0, 1, 0];
+            test_vm.pc = 0;
+            test_vm.run_once().unwrap();
+            assert_eq!(test_vm.equal, expected, "opcode {}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_jeq_opcode() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = 7;
+        test_vm.equal = true;
+        test_vm.program = vec![15, 0, 0, 0, 17, 0, 0, 0, 17, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 7);
+    }
+
+    #[test]
+    fn test_aloc_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 1024;
+        test_vm.flags.carry = true;
+        test_vm.program = vec![17, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.heap.len(), 1024);
+        assert!(!test_vm.flags().carry);
+    }
+
+    #[test]
+    fn test_aloc_opcode_below_zero() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 8];
+        test_vm.registers[0] = -1;
+        test_vm.program = vec![17, 0];
+        assert_eq!(test_vm.run_once(), Ok(ExitReason::Stepped));
+        assert_eq!(test_vm.heap.len(), 8);
+        assert!(test_vm.flags().carry);
+    }
+
+    #[test]
+    fn test_aloc_beyond_heap_limit() {
+        let mut test_vm = VM::new();
+        test_vm.set_heap_limit(100);
+        test_vm.registers[0] = 60;
+        test_vm.registers[1] = 12;
+        // aloc $0; aloc $0; jmpc $1; hlt; hlt
+        test_vm.program = vec![17, 0, 17, 0, 43, 1, 6, 6, 6, 6, 6, 6, 6];
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.pc, 13);
+        assert_eq!(test_vm.heap.len(), 60);
+
+        test_vm.registers[0] = 40;
+        test_vm.pc = 0;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.heap.len(), 100);
+        assert!(!test_vm.flags().carry);
+    }
+
+    #[test]
+    fn test_aloc_without_heap_limit() {
+        let mut test_vm = get_test_vm();
+        test_vm.set_heap_limit(usize::MAX);
+        test_vm.registers[0] = 1024;
+        test_vm.program = vec![17, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.heap.len(), 1024);
+        assert!(!test_vm.flags().carry);
+    }
+
+    #[test]
+    fn test_aloc_huge_size() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = i32::MAX;
+        test_vm.program = vec![17, 0];
+        test_vm.run().unwrap();
+        assert!(test_vm.heap.is_empty());
+        assert!(test_vm.flags().carry);
+    }
+
+    #[test]
+    fn test_add_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![1, 0, 1, 2];
+        assert_eq!(test_vm.run_once(), Ok(ExitReason::Stepped));
+        assert_eq!(test_vm.registers[2], 15);
+    }
+
+    #[test]
+    fn test_div_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 17;
+        test_vm.program = vec![4, 0, 1, 2];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[2], 1);
+        assert_eq!(test_vm.remainder, 7);
+    }
+
+    #[test]
+    fn test_sub_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![2, 0, 1, 2, 2, 1, 0, 3];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[2], -5);
+        assert_eq!(test_vm.registers[3], 5);
+    }
+
+    #[test]
+    fn test_mod_rem_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -17;
+        test_vm.registers[1] = 5;
+        test_vm.program = vec![38, 0, 1, 2, 4, 0, 1, 3, 40, 4];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[2], -2);
+        assert_eq!(test_vm.registers[3], -3);
+        assert_eq!(test_vm.registers[4], -2);
+    }
+
+    #[test]
+    fn test_mod_by_zero() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 0;
+        test_vm.program = vec![38, 0, 1, 2];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::DivisionByZero, 0, 38))
+        );
+    }
+
+    #[test]
+    fn test_bitwise_opcodes() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = 0b1100;
+        test_vm.registers[1] = 0b1010;
+        test_vm.program = vec![31, 0, 1, 2, 32, 0, 1, 3, 33, 0, 1, 4, 34, 0, 5];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[2], 0b1000);
+        assert_eq!(test_vm.registers[3], 0b1110);
+        assert_eq!(test_vm.registers[4], 0b0110);
+        assert_eq!(test_vm.registers[5], !0b1100);
+    }
+
+    #[test]
+    fn test_shift_opcodes() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = -16;
+        test_vm.registers[1] = 2;
+        test_vm.registers[6] = 33;
+        test_vm.program = vec![35, 0, 1, 2, 36, 0, 1, 3, 37, 0, 1, 4, 35, 1, 6, 5];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[2], -64);
+        assert_eq!(test_vm.registers[3], 0x3FFF_FFFC);
+        assert_eq!(test_vm.registers[4], -4);
+        assert_eq!(test_vm.registers[5], 4);
+    }
+
+    #[test]
+    fn test_mov_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![39, 1, 7];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[7], 10);
+        assert_eq!(test_vm.registers[1], 10);
+    }
+
+    #[test]
+    fn test_add_wraps_and_sets_flags() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = i32::MAX;
+        test_vm.registers[1] = 1;
+        test_vm.registers[2] = -1;
+        test_vm.program = vec![1, 0, 1, 3, 1, 2, 1, 4];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[3], i32::MIN);
+        assert_eq!(
+            test_vm.flags(),
+            Flags {
+                zero: false,
+                carry: false,
+                overflow: true,
+                negative: true,
+            }
+        );
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[4], 0);
+        assert_eq!(
+            test_vm.flags(),
+            Flags {
+                zero: true,
+                carry: true,
+                overflow: false,
+                negative: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sub_mul_div_wrap() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = i32::MIN;
+        test_vm.registers[1] = -1;
+        test_vm.registers[2] = 1;
+        test_vm.program = vec![2, 0, 2, 3, 3, 0, 1, 4, 4, 0, 1, 5, 2, 2, 1, 6];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[3], i32::MAX);
+        assert!(test_vm.flags().overflow);
+        assert!(!test_vm.flags().carry);
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[4], i32::MIN);
+        assert!(test_vm.flags().overflow);
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[5], i32::MIN);
+        assert!(test_vm.flags().overflow);
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[6], 2);
+        assert!(test_vm.flags().carry);
+        assert!(!test_vm.flags().overflow);
+    }
+
+    #[test]
+    fn test_bitwise_opcodes_clear_carry() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = -1;
+        test_vm.registers[1] = 1;
+        test_vm.program = vec![1, 0, 1, 2, 31, 0, 2, 3];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.flags(), Flags::new(0, false, false));
+    }
+
+    #[test]
+    fn test_flag_jumps() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = -1;
+        test_vm.registers[1] = 1;
+        test_vm.registers[5] = 12;
+        // add $0 $1 $2; jmpnz $5; jmpc $5; hlt; hlt
+        test_vm.program = vec![1, 0, 1, 2, 42, 5, 43, 5, 6, 6, 6, 6, 6];
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.pc, 13);
+
+        for (opcode, taken) in &[
+            (41, true),
+            (44, false),
+            (45, false),
+            (46, true),
+            (47, false),
+            (48, true),
+        ] {
+            test_vm.pc = 4;
+            test_vm.program[4] = *opcode;
+            test_vm.run_once().unwrap();
+            assert_eq!(test_vm.pc == 12, *taken, "opcode {}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        let mut test_vm = VM::new();
+        let mut program = vec![49, 0];
+        program.extend_from_slice(&1.5f64.to_be_bytes());
+        program.extend_from_slice(&[49, 1]);
+        program.extend_from_slice(&(-0.25f64).to_be_bytes());
+        // fadd, fsub, fmul and fdiv of $f0 and $f1 into $f2 to $f5
+        program.extend_from_slice(&[50, 0, 1, 2, 51, 0, 1, 3, 52, 0, 1, 4, 53, 0, 1, 5]);
+        test_vm.program = program;
+        test_vm.run().unwrap();
+        assert_eq!(
+            test_vm.float_registers[..6],
+            [1.5, -0.25, 1.25, 1.75, -0.375, -6.0]
+        );
+        assert_eq!(test_vm.registers, [0; REGISTER_COUNT]);
+    }
+
+    #[test]
+    fn test_float_division_by_zero() {
+        let mut test_vm = VM::new();
+        test_vm.float_registers[0] = 1.0;
+        test_vm.program = vec![53, 0, 1, 2];
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.float_registers[2], f64::INFINITY);
+    }
+
+    #[test]
+    fn test_float_comparisons() {
+        let mut test_vm = VM::new();
+        test_vm.float_registers[0] = 0.1 + 0.2;
+        test_vm.float_registers[1] = 0.3;
+        for (opcode, expected) in &[
+            (54, false),
+            (55, true),
+            (56, false),
+            (57, false),
+            (58, true),
+            (59, true),
+        ] {
+            test_vm.pc = 0;
+            test_vm.program = vec![*opcode, 0, 1, 0];
+            test_vm.run_once().unwrap();
+            assert_eq!(test_vm.equal, *expected, "opcode {}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_float_conversions() {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = -7;
+        test_vm.float_registers[1] = -2.9;
+        test_vm.float_registers[2] = 1e12;
+        test_vm.float_registers[3] = f64::NAN;
+        test_vm.program = vec![60, 0, 0, 61, 1, 1, 61, 2, 2, 61, 3, 3];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.float_registers[0], -7.0);
+        assert_eq!(test_vm.registers[1..4], [-2, i32::MAX, 0]);
+    }
+
+    #[test]
+    fn test_float_register_out_of_range() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![50, 0, 1, 32];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::RegisterOutOfRange(32), 0, 50))
+        );
+    }
+
+    #[test]
+    fn test_syscall_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.register_host_fn(7, |vm: &mut VM| {
+            vm.registers[2] = vm.registers[0] * vm.registers[1];
+            vm.heap.push(1);
+            Ok(ExitReason::Stepped)
+        });
+        test_vm.program = vec![62, 0, 7, 62, 0, 3];
+        test_vm.register_default_host_fns();
+        test_vm.registers[0] = 5;
+        assert_eq!(test_vm.run(), Ok(ExitReason::Exited(5)));
+        assert_eq!(test_vm.registers[2], 50);
+        assert_eq!(test_vm.heap(), &[1]);
+        assert_eq!(test_vm.pc, 6);
+    }
+
+    #[test]
+    fn test_syscall_unknown_host_fn() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![62, 1, 0];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::UnknownHostFunction(256), 0, 62))
+        );
+    }
+
+    #[test]
+    fn test_host_fn_error() {
+        let mut test_vm = VM::new();
+        test_vm.register_host_fn(0, |_: &mut VM| {
+            Err(VmErrorKind::HostError("no".to_string()))
+        });
+        test_vm.program = vec![62, 0, 0, 62, 0, 0];
+        assert!(test_vm.run().is_err());
+        test_vm.pc = 3;
+        assert!(test_vm.run().is_err(), "function is kept after failing");
+    }
+
+    #[test]
+    fn test_with_program_and_reset() {
+        let mut test_vm = VM::with_program(vec![0, 0, 0, 9, 18, 0, 6]);
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.registers()[0], 9);
+        assert_eq!(test_vm.stack, vec![9]);
+
+        test_vm.reset();
+        assert_eq!(test_vm.pc(), 0);
+        assert_eq!(test_vm.registers()[0], 0);
+        assert!(test_vm.stack.is_empty());
+        assert_eq!(test_vm.program(), &[0, 0, 0, 9, 18, 0, 6]);
+
+        test_vm.load_program(vec![6]);
+        test_vm.registers_mut()[3] = 4;
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.registers()[3], 4);
+    }
+
+    #[test]
+    fn test_run_until() {
+        // jmp $0 with $0 = 0 loops forever
+        let mut test_vm = VM::with_program(vec![5, 0]);
+        assert_eq!(test_vm.run_until(1000), Ok(ExitReason::Stepped));
+        assert_eq!(test_vm.pc(), 0);
+
+        let mut test_vm = VM::with_program(vec![0, 0, 0, 1, 6]);
+        assert_eq!(test_vm.run_until(1), Ok(ExitReason::Stepped));
+        assert_eq!(test_vm.run_until(1), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.run_until(0), Ok(ExitReason::Stepped));
+    }
+
+    #[test]
+    fn test_run_with_fuel() {
+        // load $1 #4; jmp $1 loops on the jmp forever
+        let mut test_vm = VM::with_program(vec![0, 1, 0, 4, 5, 1]);
+        assert_eq!(test_vm.run_with_fuel(100), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.fuel_used(), 100);
+        assert_eq!(test_vm.pc(), 4);
+        assert_eq!(test_vm.run_with_fuel(0), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.fuel_used(), 100);
+    }
+
+    #[test]
+    fn test_run_with_fuel_stops_free_loops() {
+        let mut test_vm = VM::with_program(vec![0, 1, 0, 4, 5, 1]);
+        test_vm.set_cost_table(CostTable::uniform(0));
+        assert_eq!(test_vm.run_with_fuel(10), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.fuel_used(), 10);
+    }
+
+    #[test]
+    fn test_run_with_fuel_resumes() {
+        let mut costs = CostTable::default();
+        costs.set_cost(&Opcode::ADD, 5);
+        // add $0 $1 $2 twice, then hlt
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![1, 0, 1, 2, 1, 2, 1, 3, 6];
+        test_vm.set_cost_table(costs);
+
+        assert_eq!(test_vm.run_with_fuel(9), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.registers[2], 15);
+        assert_eq!(test_vm.registers[3], 0);
+        assert_eq!(test_vm.pc, 4);
+
+        assert_eq!(test_vm.run_with_fuel(5), Ok(ExitReason::OutOfFuel));
+        assert_eq!(test_vm.registers[3], 25);
+        assert_eq!(test_vm.run_with_fuel(1), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.fuel_used(), 11);
+    }
+
+    #[test]
+    fn test_truncated_load() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![0];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::TruncatedInstruction, 0, 0))
+        );
+    }
+
+    #[test]
+    fn test_truncated_add_leaves_registers_untouched() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![6, 1, 0, 1];
+        test_vm.pc = 1;
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::TruncatedInstruction, 1, 1))
+        );
+        assert_eq!(test_vm.registers, get_test_vm().registers);
+    }
+
+    #[test]
+    fn test_div_by_zero() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 0;
+        test_vm.program = vec![6, 4, 0, 1, 2];
+        test_vm.pc = 1;
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::DivisionByZero, 1, 4))
+        );
+    }
+
+    #[test]
+    fn test_register_out_of_range() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![0, 32, 0, 1];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::RegisterOutOfRange(32), 0, 0))
+        );
+    }
+
+    #[test]
+    fn test_jmpb_before_start() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![8, 0];
+        assert_eq!(
+            test_vm.run_once(),
+            Err(VmError::new(VmErrorKind::InvalidJumpTarget(-3), 0, 8))
+        );
+    }
+
+    #[test]
+    fn test_push_pop_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![18, 0, 18, 1, 19, 2, 19, 3];
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.registers[2], 10);
+        assert_eq!(test_vm.registers[3], 5);
+        assert!(test_vm.stack.is_empty());
+    }
+
+    #[test]
+    fn test_pop_empty_stack() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![19, 0];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::StackUnderflow, 0, 19))
+        );
+    }
+
+    #[test]
+    fn test_push_stack_overflow() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 4;
+        // push $0; jmpb $1
+        test_vm.program = vec![18, 0, 8, 1];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::StackOverflow, 0, 18))
+        );
+        assert_eq!(test_vm.stack.len(), STACK_LIMIT);
+    }
+
+    #[test]
+    fn test_call_ret_opcodes() {
+        let mut test_vm = get_test_vm();
+        // call #4; hlt; load $0 #7; ret
+        test_vm.program = vec![20, 0, 4, 6, 0, 0, 0, 7, 21];
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.registers[0], 7);
+        assert_eq!(test_vm.pc, 4);
+        assert!(test_vm.call_stack.is_empty());
+    }
+
+    #[test]
+    fn test_recursive_call() {
+        let mut test_vm = get_test_vm();
+        // Sums 5 + 4 + ... + 1 into $2 by recursing until $0 reaches zero, counting down by $1.
+        test_vm.registers[1] = 1;
+        test_vm.registers[3] = 21;
+        test_vm.program = vec![
+            20, 0, 4, // 0: call #4
+            6, // 3: hlt
+            1, 2, 0, 2, // 4: add $2 $0 $2
+            2, 0, 1, 0, // 8: sub $0 $1 $0
+            9, 0, 4, 0, // 12: eq $0 $4
+            15, 3, // 16: jeq $3
+            20, 0, 4,  // 18: call #4
+            21, // 21: ret
+        ];
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.registers[2], 15);
+        assert_eq!(test_vm.registers[0], 0);
+    }
+
+    #[test]
+    fn test_ret_without_call() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![21];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::StackUnderflow, 0, 21))
+        );
+    }
+
+    #[test]
+    fn test_call_stack_overflow() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![20, 0, 0];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::StackOverflow, 0, 20))
+        );
+        assert_eq!(test_vm.call_stack.len(), STACK_LIMIT);
+    }
+
+    #[test]
+    fn test_storeb_loadb_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 16];
+        test_vm.registers[2] = 3;
+        test_vm.registers[3] = 0x1ff;
+        // storeb $2 $3; loadb $4 $2
+        test_vm.program = vec![23, 2, 3, 22, 4, 2];
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.heap[3], 0xff);
+        assert_eq!(test_vm.registers[4], 0xff);
+    }
+
+    #[test]
+    fn test_storew_loadw_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 8;
+        test_vm.registers[2] = 4;
+        test_vm.registers[3] = -123_456;
+        // aloc $0; storew $2 $3; loadw $4 $2
+        test_vm.program = vec![17, 0, 25, 2, 3, 24, 4, 2];
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(&test_vm.heap[4..8], &(-123_456i32).to_be_bytes());
+        assert_eq!(test_vm.registers[4], -123_456);
+    }
+
+    #[test]
+    fn test_loadb_past_heap_end() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 16];
+        test_vm.registers[2] = 16;
+        test_vm.program = vec![22, 4, 2];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::HeapOutOfBounds(16), 0, 22))
+        );
+    }
+
+    #[test]
+    fn test_storew_straddling_heap_end() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 16];
+        test_vm.registers[2] = 14;
+        test_vm.program = vec![25, 2, 0];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::HeapOutOfBounds(14), 0, 25))
+        );
+        assert_eq!(test_vm.heap, vec![0; 16]);
+    }
+
+    #[test]
+    fn test_loadw_negative_address() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 16];
+        test_vm.registers[2] = -1;
+        test_vm.program = vec![24, 4, 2];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::HeapOutOfBounds(-1), 0, 24))
+        );
+    }
+
+    #[test]
+    fn test_shrink_free_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 16];
+        test_vm.program = vec![27, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.heap.len(), 11);
+        test_vm.registers[0] = 12;
+        test_vm.pc = 0;
+        assert_eq!(
+            test_vm.run_once(),
+            Err(VmError::new(VmErrorKind::HeapOverflow, 0, 27))
+        );
+        test_vm.program = vec![26];
+        test_vm.pc = 0;
+        test_vm.run_once().unwrap();
+        assert!(test_vm.heap.is_empty());
+    }
+
+    #[test]
+    fn test_loadcb_loadcw_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.set_ro_data(vec![b'h', b'i', 0, 0, 1, 0, 2]);
+        test_vm.registers[2] = 1;
+        test_vm.registers[3] = 3;
+        // loadcb $4 $2; loadcw $5 $3
+        test_vm.program = vec![28, 4, 2, 29, 5, 3];
+        assert_eq!(test_vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(test_vm.registers[4], i32::from(b'i'));
+        assert_eq!(test_vm.registers[5], 0x10002);
+    }
+
+    #[test]
+    fn test_loadcw_past_data_end() {
+        let mut test_vm = get_test_vm();
+        test_vm.set_ro_data(vec![0; 6]);
+        test_vm.registers[2] = 3;
+        test_vm.program = vec![29, 4, 2];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::DataOutOfBounds(3), 0, 29))
+        );
+    }
+
+    #[test]
+    fn test_load_bytecode() {
+        let file = BytecodeFile {
+            entry_point: 1,
+            code: vec![6, 28, 0, 1, 6],
+            data: vec![0, 42],
+            symbols: None,
+        };
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 1;
+        test_vm.load_bytecode(&file.to_bytes()).unwrap();
+        assert_eq!(test_vm.pc, 1);
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(test_vm.registers[0], 42);
+    }
+
+    #[test]
+    fn test_load_bytecode_refuses_raw_bytes() {
+        let mut test_vm = get_test_vm();
+        assert_eq!(
+            test_vm.load_bytecode(&[0, 0, 1, 244]),
+            Err(BytecodeError::BadMagic)
+        );
+        assert!(test_vm.program.is_empty());
+    }
+
+    #[test]
+    fn test_tracer_sees_register_changes() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let seen = Rc::clone(&events);
+        let mut test_vm = get_test_vm();
+        test_vm.set_tracer(move |event: &TraceEvent| seen.borrow_mut().push(event.clone()));
+        test_vm.program = vec![1, 0, 1, 2, 60, 2, 3, 6];
+        assert_eq!(test_vm.run(), Ok(ExitReason::Halted));
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].pc, 0);
+        assert_eq!(events[0].opcode, Opcode::ADD);
+        assert_eq!(events[0].operands, vec![0, 1, 2]);
+        assert_eq!(
+            events[0].changes,
+            vec![RegisterChange::Integer {
+                register: 2,
+                old: 0,
+                new: 15
+            }]
+        );
+        assert_eq!(
+            events[1].changes,
+            vec![RegisterChange::Float {
+                register: 3,
+                old: 0.0,
+                new: 15.0
+            }]
+        );
+        assert_eq!(events[2].opcode, Opcode::HLT);
+        assert!(events[2].operands.is_empty());
+        assert_eq!(events[2].result, Ok(ExitReason::Halted));
+        assert!(test_vm.remove_tracer().is_some());
+    }
+
+    #[test]
+    fn test_tracer_sees_faults() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let seen = Rc::clone(&events);
+        let mut test_vm = VM::new();
+        test_vm.set_tracer(move |event: &TraceEvent| seen.borrow_mut().push(event.clone()));
+        test_vm.program = vec![4, 0, 1, 2];
+        assert_eq!(
+            test_vm.run(),
+            Err(VmError::new(VmErrorKind::DivisionByZero, 0, 4))
+        );
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].opcode, Opcode::DIV);
+        assert_eq!(events[0].operands, vec![0, 1, 2]);
+        assert_eq!(events[0].result, Err(VmErrorKind::DivisionByZero));
+    }
+}
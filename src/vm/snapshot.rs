@@ -0,0 +1,309 @@
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+use crate::vm::{Flags, FLOAT_REGISTER_COUNT, REGISTER_COUNT, STACK_LIMIT, VM};
+
+/// First bytes of every snapshot written by `VM::snapshot`.
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"VIRS";
+
+/// Snapshot format version written by `VM::snapshot` and the only one `VM::restore` accepts.
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+// Layout, all integers big-endian like the bytecode format:
+//
+//  magic            4 bytes
+//  version          u16
+//  reserved         u16
+//  registers        32 x i32
+//  float registers  32 x u64, the bits of each f64
+//  pc               u32
+//  remainder        u32
+//  equal            u8, 0 or 1
+//  flags            u8, bit 0 zero, bit 1 carry, bit 2 overflow, bit 3 negative
+//  heap limit       u64
+//  fuel used        u64
+//  program          u32 length, bytes
+//  read-only data   u32 length, bytes
+//  heap             u32 length, bytes
+//  stack            u32 count, i32 each
+//  call stack       u32 count, u32 return address each
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SnapshotError {
+    /// The input does not start with `SNAPSHOT_MAGIC`.
+    BadMagic,
+    /// The snapshot was written by a different version of the format.
+    UnsupportedVersion(u16),
+    /// The input ends before the snapshot is complete.
+    Truncated,
+    /// There are bytes left after the snapshot.
+    TrailingBytes,
+    /// A field holds a value the VM cannot be in, named by the field.
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a virian snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::TrailingBytes => write!(f, "unexpected bytes after the snapshot"),
+            SnapshotError::InvalidField(field) => write!(f, "invalid {} in snapshot", field),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl VM {
+    /// Serializes the execution state: registers, program counter, program, read-only data, heap
+    /// and its limit, remainder, equality bit, flags, both stacks and the fuel used. Host
+    /// functions, the tracer and the cost table are not part of it.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut results = vec![];
+        results.extend_from_slice(&SNAPSHOT_MAGIC);
+        results.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+        results.extend_from_slice(&0u16.to_be_bytes());
+        for register in &self.registers {
+            results.extend_from_slice(&register.to_be_bytes());
+        }
+        for register in &self.float_registers {
+            results.extend_from_slice(&register.to_bits().to_be_bytes());
+        }
+        results.extend_from_slice(&(self.pc as u32).to_be_bytes());
+        results.extend_from_slice(&self.remainder.to_be_bytes());
+        results.push(self.equal as u8);
+        results.push(flag_bits(self.flags));
+        results.extend_from_slice(&(self.heap_limit as u64).to_be_bytes());
+        results.extend_from_slice(&self.fuel_used.to_be_bytes());
+        for bytes in &[&self.program, &self.ro_data, &self.heap] {
+            results.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+            results.extend_from_slice(bytes);
+        }
+        results.extend_from_slice(&(self.stack.len() as u32).to_be_bytes());
+        for value in &self.stack {
+            results.extend_from_slice(&value.to_be_bytes());
+        }
+        results.extend_from_slice(&(self.call_stack.len() as u32).to_be_bytes());
+        for &address in &self.call_stack {
+            results.extend_from_slice(&(address as u32).to_be_bytes());
+        }
+        results
+    }
+
+    /// Replaces the execution state with one written by `snapshot`, so the program continues
+    /// where it was taken. Host functions, the tracer and the cost table stay as they are. On
+    /// error the VM is left unchanged.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        if !bytes.starts_with(&SNAPSHOT_MAGIC) {
+            return Err(SnapshotError::BadMagic);
+        }
+        let mut reader = Reader(&bytes[SNAPSHOT_MAGIC.len()..]);
+        let version = reader.u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        reader.u16()?;
+
+        let mut registers = [0; REGISTER_COUNT];
+        for register in registers.iter_mut() {
+            *register = reader.u32()? as i32;
+        }
+        let mut float_registers = [0.0; FLOAT_REGISTER_COUNT];
+        for register in float_registers.iter_mut() {
+            *register = f64::from_bits(reader.u64()?);
+        }
+        let pc = reader.u32()? as usize;
+        let remainder = reader.u32()?;
+        let equal = match reader.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(SnapshotError::InvalidField("equality bit")),
+        };
+        let flags = match reader.u8()? {
+            bits if bits < 16 => Flags {
+                zero: bits & 1 != 0,
+                carry: bits & 2 != 0,
+                overflow: bits & 4 != 0,
+                negative: bits & 8 != 0,
+            },
+            _ => return Err(SnapshotError::InvalidField("flags")),
+        };
+        let heap_limit = reader.u64()? as usize;
+        let fuel_used = reader.u64()?;
+        let program = reader.bytes()?;
+        let ro_data = reader.bytes()?;
+        let heap = reader.bytes()?;
+        let stack = reader.list(|reader| Ok(reader.u32()? as i32))?;
+        let call_stack = reader.list(|reader| Ok(reader.u32()? as usize))?;
+        if !reader.0.is_empty() {
+            return Err(SnapshotError::TrailingBytes);
+        }
+
+        if pc > program.len() {
+            return Err(SnapshotError::InvalidField("program counter"));
+        }
+        if stack.len() > STACK_LIMIT {
+            return Err(SnapshotError::InvalidField("stack"));
+        }
+        if call_stack.len() > STACK_LIMIT || call_stack.iter().any(|&a| a > program.len()) {
+            return Err(SnapshotError::InvalidField("call stack"));
+        }
+
+        self.registers = registers;
+        self.float_registers = float_registers;
+        self.pc = pc;
+        self.remainder = remainder;
+        self.equal = equal;
+        self.flags = flags;
+        self.heap_limit = heap_limit;
+        self.fuel_used = fuel_used;
+        self.program = program;
+        self.ro_data = ro_data;
+        self.heap = heap;
+        self.stack = stack;
+        self.call_stack = call_stack;
+        Ok(())
+    }
+}
+
+fn flag_bits(flags: Flags) -> u8 {
+    flags.zero as u8
+        | (flags.carry as u8) << 1
+        | (flags.overflow as u8) << 2
+        | (flags.negative as u8) << 3
+}
+
+/// Cursor over the part of a snapshot not read yet.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Bytes preceded by their u32 length.
+    fn bytes(&mut self) -> Result<Vec<u8>, SnapshotError> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Items preceded by their u32 count.
+    fn list<T>(
+        &mut self,
+        item: impl Fn(&mut Self) -> Result<T, SnapshotError>,
+    ) -> Result<Vec<T>, SnapshotError> {
+        let count = self.u32()? as usize;
+        // Not trusted for the capacity, a corrupt count would allocate before failing
+        let mut items = vec![];
+        for _ in 0..count {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::ExitReason;
+
+    /// Pushes 7, calls a subroutine that allocates 4 heap bytes and stores 9 in the first,
+    /// then halts.
+    fn get_test_vm() -> VM {
+        let program = vec![
+            0, 0, 0, 7, // load $0 #7
+            18, 0, // push $0
+            20, 0, 11, // call #11
+            6,  // hlt
+            6,  // padding
+            0, 1, 0, 4, // load $1 #4
+            17, 1, // aloc $1
+            0, 2, 0, 9, // load $2 #9
+            23, 3, 2,  // storeb $3 $2
+            21, // ret
+        ];
+        let mut vm = VM::with_program(program);
+        vm.float_registers_mut()[1] = -2.5;
+        vm.set_ro_data(vec![1, 2]);
+        vm
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut vm = get_test_vm();
+        vm.run_until(5).unwrap();
+        assert_eq!(vm.call_stack(), &[9]);
+        let snapshot = vm.snapshot();
+
+        let mut copy = VM::new();
+        copy.restore(&snapshot).unwrap();
+        assert_eq!(copy.snapshot(), snapshot);
+        assert_eq!(copy.pc(), vm.pc());
+        assert_eq!(copy.float_registers()[1], -2.5);
+        assert_eq!(copy.ro_data(), &[1, 2]);
+        assert_eq!(copy.heap(), &[0, 0, 0, 0]);
+
+        assert_eq!(copy.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(copy.snapshot(), vm.snapshot());
+        assert_eq!(copy.heap(), &[9, 0, 0, 0]);
+        assert_eq!(copy.stack, vec![7]);
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_input() {
+        let mut vm = get_test_vm();
+        vm.run_once().unwrap();
+        let snapshot = vm.snapshot();
+        let mut copy = VM::new();
+
+        assert_eq!(copy.restore(b"VIRN"), Err(SnapshotError::BadMagic));
+        let mut bytes = snapshot.clone();
+        bytes[5] = 2;
+        assert_eq!(
+            copy.restore(&bytes),
+            Err(SnapshotError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            copy.restore(&snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+        let mut bytes = snapshot.clone();
+        bytes.push(0);
+        assert_eq!(copy.restore(&bytes), Err(SnapshotError::TrailingBytes));
+        let mut bytes = snapshot.clone();
+        bytes[8 + REGISTER_COUNT * 4 + FLOAT_REGISTER_COUNT * 8 + 8] = 2;
+        assert_eq!(
+            copy.restore(&bytes),
+            Err(SnapshotError::InvalidField("equality bit"))
+        );
+
+        assert_eq!(copy.snapshot(), VM::new().snapshot());
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::disassembler::instruction;
+use crate::instructions::{Opcode, OperandKind};
+use crate::vm::{ExitReason, VmErrorKind, FLOAT_REGISTER_COUNT, REGISTER_COUNT};
+
+/// An executed instruction, handed to the VM's tracer once the instruction has run or faulted.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TraceEvent {
+    /// Offset the instruction was read from.
+    pub pc: usize,
+    pub opcode: Opcode,
+    /// Operand values as `Encoding::decode` returns them, one per operand of the opcode.
+    pub operands: Vec<i64>,
+    /// Registers the instruction changed, in register order, integer registers first.
+    pub changes: Vec<RegisterChange>,
+    /// How the instruction ended, the fault if it raised one.
+    pub result: Result<ExitReason, VmErrorKind>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RegisterChange {
+    Integer { register: usize, old: i32, new: i32 },
+    Float { register: usize, old: f64, new: f64 },
+}
+
+impl RegisterChange {
+    /// Changes between two copies of the register files.
+    pub fn between(
+        old: (&[i32; REGISTER_COUNT], &[f64; FLOAT_REGISTER_COUNT]),
+        new: (&[i32; REGISTER_COUNT], &[f64; FLOAT_REGISTER_COUNT]),
+    ) -> Vec<RegisterChange> {
+        let integers = old.0.iter().zip(new.0.iter()).enumerate();
+        let floats = old.1.iter().zip(new.1.iter()).enumerate();
+        integers
+            .filter(|(_, (old, new))| old != new)
+            .map(|(register, (&old, &new))| RegisterChange::Integer { register, old, new })
+            .chain(
+                floats
+                    // Compared by bits so a NaN written over a NaN is not reported
+                    .filter(|(_, (old, new))| old.to_bits() != new.to_bits())
+                    .map(|(register, (&old, &new))| RegisterChange::Float { register, old, new }),
+            )
+            .collect()
+    }
+
+    fn register_name(&self) -> String {
+        match self {
+            RegisterChange::Integer { register, .. } => format!("${}", register),
+            RegisterChange::Float { register, .. } => format!("$f{}", register),
+        }
+    }
+}
+
+// Written as `$3: 5 -> 8`
+impl fmt::Display for RegisterChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.register_name())?;
+        match self {
+            RegisterChange::Integer { old, new, .. } => write!(f, "{} -> {}", old, new),
+            RegisterChange::Float { old, new, .. } => write!(f, "{:?} -> {:?}", old, new),
+        }
+    }
+}
+
+/// Receives every instruction the VM executes, see `VM::set_tracer`. Closures taking a
+/// `&TraceEvent` are tracers too.
+pub trait Tracer {
+    fn trace(&mut self, event: &TraceEvent);
+
+    /// Writes out anything buffered and reports errors `trace` had to keep to itself.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&TraceEvent)> Tracer for F {
+    fn trace(&mut self, event: &TraceEvent) {
+        self(event)
+    }
+}
+
+impl fmt::Debug for dyn Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tracer")
+    }
+}
+
+/// Line format of a `TraceWriter`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TraceFormat {
+    /// The offset, the instruction as assembly and the registers it changed or the fault it
+    /// raised after a `;`: `000c: add $0 $0 $0 ; $0: 3 -> 6`
+    Text,
+    /// One JSON object per line, with `fault` null unless the instruction faulted:
+    /// `{"pc":12,"opcode":"add","operands":[0,0,0],`
+    /// `"changes":[{"register":"$0","old":3,"new":6}],"fault":null}`
+    JsonLines,
+}
+
+/// Tracer writing one line per instruction. The first write error stops the trace and is
+/// returned by `flush`.
+#[derive(Debug)]
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: TraceFormat) -> Self {
+        TraceWriter {
+            out,
+            format,
+            error: None,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write_text(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let instruction = instruction(event.opcode.clone(), &event.operands);
+        write!(self.out, "{:04x}: {}", event.pc, instruction)?;
+        let mut notes: Vec<String> = event.changes.iter().map(|c| c.to_string()).collect();
+        if let Err(kind) = &event.result {
+            notes.push(format!("fault: {}", kind));
+        }
+        if !notes.is_empty() {
+            write!(self.out, " ; {}", notes.join(", "))?;
+        }
+        writeln!(self.out)
+    }
+
+    fn write_json(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let operands: Vec<String> = event
+            .opcode
+            .encoding()
+            .operands
+            .iter()
+            .zip(&event.operands)
+            .map(|(kind, &value)| match kind {
+                OperandKind::Float => json_float(f64::from_bits(value as u64)),
+                _ => value.to_string(),
+            })
+            .collect();
+        let changes: Vec<String> = event
+            .changes
+            .iter()
+            .map(|change| {
+                let (old, new) = match *change {
+                    RegisterChange::Integer { old, new, .. } => (old.to_string(), new.to_string()),
+                    RegisterChange::Float { old, new, .. } => (json_float(old), json_float(new)),
+                };
+                format!(
+                    "{{\"register\":\"{}\",\"old\":{},\"new\":{}}}",
+                    change.register_name(),
+                    old,
+                    new
+                )
+            })
+            .collect();
+        let fault = match &event.result {
+            Ok(_) => "null".to_string(),
+            Err(kind) => json_string(&kind.to_string()),
+        };
+        writeln!(
+            self.out,
+            "{{\"pc\":{},\"opcode\":\"{}\",\"operands\":[{}],\"changes\":[{}],\"fault\":{}}}",
+            event.pc,
+            event.opcode.mnemonic(),
+            operands.join(","),
+            changes.join(","),
+            fault
+        )
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let written = match self.format {
+            TraceFormat::Text => self.write_text(event),
+            TraceFormat::JsonLines => self.write_json(event),
+        };
+        if let Err(e) = written {
+            self.error = Some(e);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+/// JSON has no NaN or infinities, they are written as `null`.
+fn json_float(value: f64) -> String {
+    if value.is_finite() {
+        format!("{:?}", value)
+    } else {
+        "null".to_string()
+    }
+}
+
+/// `text` as a quoted JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_event() -> TraceEvent {
+        TraceEvent {
+            pc: 12,
+            opcode: Opcode::ADD,
+            operands: vec![0, 0, 0],
+            changes: vec![
+                RegisterChange::Integer {
+                    register: 0,
+                    old: 3,
+                    new: 6,
+                },
+                RegisterChange::Float {
+                    register: 1,
+                    old: 0.0,
+                    new: f64::NAN,
+                },
+            ],
+            result: Ok(ExitReason::Stepped),
+        }
+    }
+
+    #[test]
+    fn test_register_changes_between() {
+        let mut registers = [0; REGISTER_COUNT];
+        let mut float_registers = [0.0; FLOAT_REGISTER_COUNT];
+        let old = (registers, float_registers);
+        registers[4] = -1;
+        float_registers[2] = 1.5;
+        let changes = RegisterChange::between((&old.0, &old.1), (&registers, &float_registers));
+        let text: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+        assert_eq!(text, vec!["$4: 0 -> -1", "$f2: 0.0 -> 1.5"]);
+    }
+
+    #[test]
+    fn test_write_text() {
+        let mut tracer = TraceWriter::new(vec![], TraceFormat::Text);
+        tracer.trace(&get_test_event());
+        tracer.trace(&TraceEvent {
+            pc: 16,
+            opcode: Opcode::HLT,
+            operands: vec![],
+            changes: vec![],
+            result: Ok(ExitReason::Halted),
+        });
+        tracer.trace(&TraceEvent {
+            pc: 17,
+            opcode: Opcode::DIV,
+            operands: vec![0, 1, 2],
+            changes: vec![],
+            result: Err(VmErrorKind::DivisionByZero),
+        });
+        assert_eq!(
+            String::from_utf8(tracer.into_inner()).unwrap(),
+            "000c: add $0 $0 $0 ; $0: 3 -> 6, $f1: 0.0 -> NaN\n0010: hlt\n\
+             0011: div $0 $1 $2 ; fault: division by zero\n"
+        );
+    }
+
+    #[test]
+    fn test_write_json_lines() {
+        let mut tracer = TraceWriter::new(vec![], TraceFormat::JsonLines);
+        tracer.trace(&get_test_event());
+        let mut event = get_test_event();
+        event.changes.clear();
+        event.result = Err(VmErrorKind::HostError("bad \"name\"".to_string()));
+        tracer.trace(&event);
+        assert_eq!(
+            String::from_utf8(tracer.into_inner()).unwrap(),
+            "{\"pc\":12,\"opcode\":\"add\",\"operands\":[0,0,0],\"changes\":[\
+             {\"register\":\"$0\",\"old\":3,\"new\":6},\
+             {\"register\":\"$f1\",\"old\":0.0,\"new\":null}],\"fault\":null}\n\
+             {\"pc\":12,\"opcode\":\"add\",\"operands\":[0,0,0],\"changes\":[],\
+             \"fault\":\"host function failed: bad \\\"name\\\"\"}\n"
+        );
+    }
+}